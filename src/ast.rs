@@ -0,0 +1,103 @@
+use std::os::fd::RawFd;
+
+use crate::lexer::Span;
+
+/// A whole input line: pipelines joined by `;`, `&`, `&&` and `||`
+#[derive(Debug, Default)]
+pub struct List {
+    pub items: Vec<Item>,
+}
+
+#[derive(Debug)]
+pub struct Item {
+    pub pipeline: Pipeline,
+    /// How the pipeline is separated from the next one
+    pub connector: Connector,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connector {
+    /// `;`, a newline or the end of the line
+    Seq,
+    /// `&`
+    Background,
+    /// `&&`
+    And,
+    /// `||`
+    Or,
+}
+
+/// Commands joined by `|`
+#[derive(Debug)]
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+    /// Excludes the `time` keyword
+    pub span: Span,
+    /// Preceded by `time`
+    pub timed: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Command {
+    /// `NAME=word` before the command name
+    pub assignments: Vec<(String, Word)>,
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+}
+
+#[derive(Debug)]
+pub enum Redirect {
+    /// `[n]< word`
+    Read(RawFd, Word),
+    /// `[n]> word`
+    Write(RawFd, Word),
+    /// `[n]>> word`
+    Append(RawFd, Word),
+    /// `&> word`
+    WriteAll(Word),
+    /// `[n]>&m`
+    Dup(RawFd, RawFd),
+}
+
+/// A word with its quoting removed, split into the pieces that expand
+/// differently
+#[derive(Debug)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+}
+
+impl Word {
+    /// Whether the word is exactly text, without any expansions
+    pub fn is(&self, text: &str) -> bool {
+        matches!(&self.parts[..], [WordPart::Literal(literal)] if literal == text)
+    }
+}
+
+#[derive(Debug)]
+pub enum WordPart {
+    /// Text used as is
+    Literal(String),
+    /// `~` or `~user` at the start of a word
+    Tilde(String),
+    Param(Param),
+}
+
+/// `$NAME`, `${NAME}` or `${NAME:-default}`, where NAME can also be a
+/// positional parameter or one of `?`, `#`, `@` and `!`
+#[derive(Debug)]
+pub struct Param {
+    pub name: String,
+    pub default: Option<Word>,
+    /// Inside double quotes, so an empty value still makes a field
+    pub quoted: bool,
+}
+
+impl Param {
+    pub fn new(name: String, default: Option<Word>, quoted: bool) -> Self {
+        Self {
+            name,
+            default,
+            quoted,
+        }
+    }
+}
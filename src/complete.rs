@@ -0,0 +1,372 @@
+use std::{collections::BTreeMap, fmt::Display, fs, path::PathBuf};
+
+use crate::{hash, lexer::ESCAPABLE};
+
+/// Where the words after a command name come from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Spec {
+    Files,
+    Directories,
+    /// Builtins and executables on `$PATH`
+    Commands,
+    /// `%` job specs
+    Jobs,
+    Variables,
+    /// Fixed words, like the options of a builtin
+    Words(Vec<String>),
+}
+
+/// A word the one at the cursor can be completed to
+#[derive(Debug, PartialEq)]
+pub struct Candidate {
+    /// The completed word, quoted the way the lexer reads it back
+    pub text: String,
+    /// What the list of candidates shows, the word without quotes
+    pub name: String,
+    /// Shown next to text when the candidates are listed, like the command
+    /// of a job
+    pub description: Option<String>,
+    /// A directory, which more is typed after, so no space follows it
+    pub partial: bool,
+}
+
+/// The candidates for the text between start and the cursor
+#[derive(Debug)]
+pub struct Completions {
+    pub start: usize,
+    pub candidates: Vec<Candidate>,
+}
+
+/// What completion needs to know about the shell
+#[derive(Debug)]
+pub struct Context<'a> {
+    pub builtins: &'a [&'a str],
+    /// `$PATH`
+    pub paths: String,
+    pub home: Option<String>,
+    pub variables: Vec<String>,
+    /// The job ids and commands of the jobs
+    pub jobs: Vec<(u32, String)>,
+}
+
+/// Completes the word at the cursor of the line being edited. The first word
+/// of a command is a command name, the words after it come from the specs
+/// registered for that command, or are files by default.
+#[derive(Debug)]
+pub struct Completer {
+    specs: BTreeMap<String, Vec<Spec>>,
+}
+
+/// The word the cursor is in, with its quotes removed
+#[derive(Debug, Default)]
+struct Word {
+    /// Byte offset of the word in the line
+    start: usize,
+    text: String,
+    /// The quote left open and where it is
+    quote: Option<(char, usize)>,
+    /// Byte offset in the line of the text after the last `/`
+    base: usize,
+    /// Length of the text up to and including the last `/`
+    dir: usize,
+}
+
+impl Word {
+    fn new(start: usize) -> Self {
+        Word {
+            start,
+            base: start,
+            ..Default::default()
+        }
+    }
+
+    /// Adds c, which ends at end in the line
+    fn push(&mut self, c: char, end: usize) {
+        self.text.push(c);
+        if c == '/' {
+            self.base = end;
+            self.dir = self.text.len();
+        }
+    }
+}
+
+impl Completer {
+    /// Starts out knowing the options of the builtins and what they take
+    pub fn new() -> Self {
+        let mut completer = Completer {
+            specs: BTreeMap::new(),
+        };
+        let words = |words: &str| Spec::Words(words.split(' ').map(str::to_string).collect());
+        for name in ["fg", "bg"] {
+            completer.register(name, vec![Spec::Jobs]);
+        }
+        for name in ["cd", "pushd"] {
+            completer.register(name, vec![words("-L -P"), Spec::Directories]);
+        }
+        for name in ["type", "which"] {
+            completer.register(name, vec![Spec::Commands]);
+        }
+        for name in ["export", "unset"] {
+            completer.register(name, vec![Spec::Variables]);
+        }
+        completer.register("jobs", vec![words("-l -p -r -s -v -x --json"), Spec::Jobs]);
+        completer.register("kill", vec![words("-l -s"), Spec::Jobs]);
+        completer.register("wait", vec![words("-n"), Spec::Jobs]);
+        completer.register("disown", vec![words("-a -h -r"), Spec::Jobs]);
+        completer.register("set", vec![words("-b +b -o +o notify")]);
+        completer.register("history", vec![words("-c -d")]);
+        completer.register("hash", vec![words("-r"), Spec::Commands]);
+        completer.register("pwd", vec![words("-L -P")]);
+        completer.register("dirs", vec![words("-c -l -p -v")]);
+        completer.register(
+            "complete",
+            vec![words("-c -d -f -j -p -r -v -W"), Spec::Commands],
+        );
+        completer
+    }
+
+    /// Completes the arguments of command from specs, replacing what it had
+    pub fn register(&mut self, command: &str, specs: Vec<Spec>) {
+        self.specs.insert(command.to_string(), specs);
+    }
+
+    /// Goes back to completing files for command, false if it had no specs
+    pub fn remove(&mut self, command: &str) -> bool {
+        self.specs.remove(command).is_some()
+    }
+
+    pub fn get(&self, command: &str) -> Option<&[Spec]> {
+        self.specs.get(command).map(|specs| specs.as_slice())
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = &str> {
+        self.specs.keys().map(|name| name.as_str())
+    }
+
+    /// Completes the last word of line, the text before the cursor
+    pub fn complete(&self, line: &str, context: &Context) -> Completions {
+        let (words, word, redirect) = scan(line);
+        let raw = &line[word.start..];
+        let single = matches!(word.quote, Some(('\'', _)));
+
+        // `$NAME` or `${NAME` at the end of the word
+        if let Some(i) = raw.rfind('$').filter(|_| !single) {
+            let name = raw[i + 1..].strip_prefix('{');
+            let (prefix, brace) = (name.unwrap_or(&raw[i + 1..]), name.is_some());
+            if prefix
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+            {
+                let candidates = context
+                    .variables
+                    .iter()
+                    .filter(|name| name.starts_with(prefix))
+                    .map(|name| Candidate {
+                        text: match brace {
+                            true => format!("${{{}}}", name),
+                            false => format!("${}", name),
+                        },
+                        name: format!("${}", name),
+                        description: None,
+                        partial: word.quote.is_some(),
+                    })
+                    .collect();
+                return Completions {
+                    start: word.start + i,
+                    candidates,
+                };
+            }
+        }
+
+        let mut candidates = vec![];
+        let specs = match (words.first(), redirect) {
+            (_, true) => vec![Spec::Files],
+            (None, false) if word.text.contains('/') => vec![Spec::Files],
+            (None, false) => vec![Spec::Commands],
+            (Some(_), false) if word.text.starts_with('%') => vec![Spec::Jobs],
+            (Some(command), false) => match self.specs.get(command) {
+                Some(specs) => specs.clone(),
+                None => vec![Spec::Files],
+            },
+        };
+        // files replace the text after the last `/`, everything else the word
+        let mut start = word.start;
+        for spec in specs {
+            match spec {
+                Spec::Files | Spec::Directories => {
+                    start = word.base;
+                    candidates.extend(files(&word, context, spec == Spec::Directories));
+                }
+                Spec::Commands => {
+                    let mut names: Vec<String> = context
+                        .builtins
+                        .iter()
+                        .filter(|name| name.starts_with(&word.text))
+                        .map(|name| name.to_string())
+                        .collect();
+                    names.extend(hash::commands(&word.text, &context.paths));
+                    names.sort();
+                    names.dedup();
+                    candidates.extend(names.iter().map(|name| candidate(name, &word, false)));
+                }
+                Spec::Jobs => {
+                    for (jid, cmd) in &context.jobs {
+                        let spec = format!("%{}", jid);
+                        if spec.starts_with(&word.text) {
+                            candidates.push(Candidate {
+                                description: Some(cmd.trim_end_matches([' ', '&']).to_string()),
+                                ..candidate(&spec, &word, false)
+                            });
+                        }
+                    }
+                }
+                Spec::Variables => {
+                    let names = context.variables.iter();
+                    let names = names.filter(|name| name.starts_with(&word.text));
+                    candidates.extend(names.map(|name| candidate(name, &word, false)));
+                }
+                // options are only offered once their `-` is typed
+                Spec::Words(words) => {
+                    let words = words.iter().filter(|w| {
+                        w.starts_with(&word.text)
+                            && (!word.text.is_empty() || !w.starts_with(['-', '+']))
+                    });
+                    candidates.extend(words.map(|w| candidate(w, &word, false)));
+                }
+            }
+        }
+        Completions { start, candidates }
+    }
+}
+
+impl Display for Spec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Files => write!(f, "-f"),
+            Self::Directories => write!(f, "-d"),
+            Self::Commands => write!(f, "-c"),
+            Self::Jobs => write!(f, "-j"),
+            Self::Variables => write!(f, "-v"),
+            Self::Words(words) => write!(f, "-W '{}'", words.join(" ")),
+        }
+    }
+}
+
+/// Splits line into the words of the command the cursor is in. Returns the
+/// words before the one at the cursor, that word, and whether it is the
+/// target of a redirection.
+fn scan(line: &str) -> (Vec<String>, Word, bool) {
+    let mut words = vec![];
+    let mut word: Option<Word> = None;
+    let mut redirect = false;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let end = i + c.len_utf8();
+        let quote = word.as_ref().and_then(|word| word.quote);
+        match (quote, c) {
+            (Some((quote, _)), c) if c == quote => word.as_mut().unwrap().quote = None,
+            (Some(('"', _)), '\\') | (None, '\\') => {
+                let word = word.get_or_insert_with(|| Word::new(i));
+                match chars.next() {
+                    Some((i, next)) => word.push(next, i + next.len_utf8()),
+                    None => word.push(c, end),
+                }
+            }
+            (Some(_), c) => word.as_mut().unwrap().push(c, end),
+            (None, '\'' | '"') => word.get_or_insert_with(|| Word::new(i)).quote = Some((c, i)),
+            (None, c) if c.is_whitespace() || ";|&()<>".contains(c) => {
+                if let Some(word) = word.take() {
+                    // the fd of a redirection like `2>` is not a word
+                    let fd = "<>".contains(c) && word.text.chars().all(|c| c.is_ascii_digit());
+                    match redirect {
+                        true => redirect = false,
+                        false if !fd => words.push(word.text),
+                        false => {}
+                    }
+                }
+                match c {
+                    '<' | '>' => redirect = true,
+                    c if !c.is_whitespace() => {
+                        words.clear();
+                        redirect = false;
+                    }
+                    _ => {}
+                }
+            }
+            (None, c) => word.get_or_insert_with(|| Word::new(i)).push(c, end),
+        }
+    }
+    // assignments before the command name are not words of the command
+    let assignment = |word: &String| {
+        word.split_once('=')
+            .is_some_and(|(name, _)| !name.is_empty())
+    };
+    let skip = words.iter().take_while(|word| assignment(word)).count();
+    words.drain(..skip);
+    (words, word.unwrap_or(Word::new(line.len())), redirect)
+}
+
+/// Files in the directory the word names whose names start with the rest of
+/// it. Hidden files are only offered once their `.` is typed.
+fn files(word: &Word, context: &Context, directories: bool) -> Vec<Candidate> {
+    let (dir, prefix) = word.text.split_at(word.dir);
+    let path = match (dir, dir.strip_prefix("~/"), &context.home) {
+        ("", _, _) => PathBuf::from("."),
+        (_, Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        (dir, _, _) => PathBuf::from(dir),
+    };
+    let Ok(entries) = fs::read_dir(path) else {
+        return vec![];
+    };
+    let mut names: Vec<(String, bool)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let is_dir = entry.path().is_dir();
+            let hidden = name.starts_with('.') && !prefix.starts_with('.');
+            (name.starts_with(prefix) && !hidden && (is_dir || !directories))
+                .then_some((name, is_dir))
+        })
+        .collect();
+    names.sort();
+    names
+        .into_iter()
+        .map(|(name, is_dir)| {
+            let name = match is_dir {
+                true => format!("{}/", name),
+                false => name,
+            };
+            let mut candidate = candidate(&name, word, is_dir);
+            // a quote opened before the last `/` is already in the line
+            if word.quote.is_some_and(|(_, at)| at < word.base) {
+                candidate.text.remove(0);
+            }
+            candidate
+        })
+        .collect()
+}
+
+/// Quotes text to replace word with, keeping the quote it was opened with.
+/// Unquoted, the characters the lexer treats specially get a backslash.
+fn candidate(text: &str, word: &Word, partial: bool) -> Candidate {
+    let quoted = match word.quote {
+        Some((quote, _)) if partial => format!("{}{}", quote, text),
+        Some((quote, _)) => format!("{}{}{}", quote, text, quote),
+        None => {
+            let mut quoted = String::new();
+            for c in text.chars() {
+                if ESCAPABLE.contains(c) && c != '\n' {
+                    quoted.push('\\');
+                }
+                quoted.push(c);
+            }
+            quoted
+        }
+    };
+    Candidate {
+        text: quoted,
+        name: text.to_string(),
+        description: None,
+        partial,
+    }
+}
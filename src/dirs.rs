@@ -0,0 +1,228 @@
+use std::{
+    env, fs,
+    os::unix::fs::MetadataExt,
+    path::{Component, Path, PathBuf},
+};
+
+use nix::errno::Errno;
+
+use crate::vars::Variables;
+
+/// Directories saved by `pushd`, the most recent first. The current
+/// directory is `$PWD` and is not stored here.
+#[derive(Debug)]
+pub struct DirStack {
+    stack: Vec<String>,
+}
+
+impl DirStack {
+    pub fn new() -> Self {
+        DirStack { stack: vec![] }
+    }
+
+    /// `pushd dir`: changes to dir and saves the directory it left
+    pub fn push(&mut self, vars: &mut Variables, dir: &str) -> Result<(), String> {
+        let old = pwd(vars);
+        cd(vars, dir, false)?;
+        self.stack.insert(0, old);
+        Ok(())
+    }
+
+    /// `pushd` without arguments: swaps the current directory with the one
+    /// saved last
+    pub fn exchange(&mut self, vars: &mut Variables) -> Result<(), String> {
+        if self.stack.is_empty() {
+            return Err("no other directory".to_string());
+        }
+        let old = pwd(vars);
+        cd(vars, &self.stack[0], false)?;
+        self.stack[0] = old;
+        Ok(())
+    }
+
+    /// `pushd +n`: rotates the stack so its nth entry, counting the current
+    /// directory as 0, becomes the current directory
+    pub fn rotate(&mut self, vars: &mut Variables, n: usize) -> Result<(), String> {
+        let mut all = self.all(vars);
+        if n >= all.len() {
+            return Err(format!("+{}: directory stack index out of range", n));
+        }
+        all.rotate_left(n);
+        cd(vars, &all[0], false)?;
+        self.stack = all.split_off(1);
+        Ok(())
+    }
+
+    /// `popd`: drops the current directory and changes to the one saved last,
+    /// `popd +n` drops the nth entry instead
+    pub fn pop(&mut self, vars: &mut Variables, n: usize) -> Result<(), String> {
+        if self.stack.is_empty() {
+            return Err("directory stack empty".to_string());
+        }
+        if n > self.stack.len() {
+            return Err(format!("+{}: directory stack index out of range", n));
+        }
+        if n == 0 {
+            cd(vars, &self.stack[0], false)?;
+            self.stack.remove(0);
+        } else {
+            self.stack.remove(n - 1);
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.stack.clear();
+    }
+
+    /// The current directory followed by the stack
+    pub fn all(&self, vars: &Variables) -> Vec<String> {
+        let mut all = vec![pwd(vars)];
+        all.extend(self.stack.iter().cloned());
+        all
+    }
+
+    /// Output of `dirs`, `-l` keeps `$HOME` spelled out, `-p` prints one
+    /// entry per line and `-v` numbers them
+    pub fn list(&self, vars: &Variables, long: bool, lines: bool, numbered: bool) -> String {
+        let all = self.all(vars).into_iter().map(|dir| match long {
+            true => dir,
+            false => abbreviate(vars, &dir),
+        });
+        if numbered {
+            all.enumerate()
+                .map(|(i, dir)| format!("{:2}  {}\n", i, dir))
+                .collect()
+        } else if lines {
+            all.map(|dir| dir + "\n").collect()
+        } else {
+            all.collect::<Vec<_>>().join(" ") + "\n"
+        }
+    }
+}
+
+/// Makes sure `$PWD` names the current directory when the shell starts
+pub fn init(vars: &mut Variables) {
+    let valid = match (vars.get("PWD"), env::current_dir()) {
+        (Some(pwd), Ok(cwd)) => Path::new(pwd).is_absolute() && same_file(Path::new(pwd), &cwd),
+        _ => false,
+    };
+    if !valid {
+        if let Ok(cwd) = env::current_dir() {
+            vars.set("PWD", cwd.display().to_string());
+        }
+    }
+    vars.export("PWD");
+}
+
+/// The logical current directory
+pub fn pwd(vars: &Variables) -> String {
+    match vars.get("PWD") {
+        Some(pwd) => pwd.to_string(),
+        None => physical(),
+    }
+}
+
+/// The current directory with every symlink resolved
+pub fn physical() -> String {
+    match env::current_dir() {
+        Ok(cwd) => cwd.display().to_string(),
+        Err(_) => ".".to_string(),
+    }
+}
+
+/// Changes the current directory and updates `$PWD` and `$OLDPWD`. Unless
+/// physical is set, `..` removes the last component of `$PWD` instead of
+/// following the symlink back. Returns the new directory.
+pub fn cd(vars: &mut Variables, dir: &str, physical: bool) -> Result<String, String> {
+    let old = pwd(vars);
+    let path = if Path::new(dir).is_absolute() {
+        PathBuf::from(dir)
+    } else {
+        Path::new(&old).join(dir)
+    };
+    let target = if physical {
+        path.clone()
+    } else {
+        normalize(&path)
+    };
+    // a logical path that doesn't exist, like `link/..` after the link's
+    // target was removed, falls back to the physical one
+    let physical = match env::set_current_dir(&target) {
+        Ok(_) => physical,
+        Err(_) if !physical => match env::set_current_dir(&path) {
+            Ok(_) => true,
+            Err(e) => return Err(error(dir, e)),
+        },
+        Err(e) => return Err(error(dir, e)),
+    };
+    let new = if physical {
+        self::physical()
+    } else {
+        target.display().to_string()
+    };
+    vars.set("OLDPWD", old);
+    vars.set("PWD", new.clone());
+    Ok(new)
+}
+
+/// Finds dir on `$CDPATH`. Returns the path to change to and whether it
+/// came from `$CDPATH`, in which case `cd` prints it.
+pub fn search(vars: &Variables, dir: &str) -> (String, bool) {
+    let relative = !dir.starts_with('/')
+        && !matches!(
+            Path::new(dir).components().next(),
+            Some(Component::CurDir | Component::ParentDir)
+        );
+    if let (true, Some(cdpath)) = (relative, vars.get("CDPATH")) {
+        for entry in cdpath.split(':') {
+            let candidate = match entry {
+                "" => Path::new(".").join(dir),
+                entry => Path::new(entry).join(dir),
+            };
+            if candidate.is_dir() {
+                return (candidate.display().to_string(), !entry.is_empty());
+            }
+        }
+    }
+    (dir.to_string(), false)
+}
+
+fn error(dir: &str, e: std::io::Error) -> String {
+    let errno = Errno::from_raw(e.raw_os_error().unwrap_or(0));
+    format!("{}: {}", dir, errno.desc())
+}
+
+/// Resolves `.` and `..` without looking at the file system
+fn normalize(path: &Path) -> PathBuf {
+    let mut res = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                res.pop();
+            }
+            Component::Normal(name) => res.push(name),
+            _ => {}
+        }
+    }
+    res
+}
+
+/// Replaces a leading `$HOME` with `~`
+pub fn abbreviate(vars: &Variables, dir: &str) -> String {
+    match vars.get("HOME") {
+        Some(home) if !home.is_empty() && home != "/" => match dir.strip_prefix(home) {
+            Some("") => "~".to_string(),
+            Some(rest) if rest.starts_with('/') => format!("~{}", rest),
+            _ => dir.to_string(),
+        },
+        _ => dir.to_string(),
+    }
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
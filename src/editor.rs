@@ -0,0 +1,695 @@
+use std::{
+    fmt::Display,
+    io::{self, stdin, stdout, Write},
+    sync::Mutex,
+};
+
+use nix::{
+    errno::Errno,
+    libc::{self, STDIN_FILENO},
+    sys::termios::{tcgetattr, tcsetattr, InputFlags, LocalFlags, SetArg, SpecialCharacterIndices},
+    unistd::read,
+};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::complete::{Candidate, Completions};
+
+/// Reads command lines from the terminal in raw mode, with Emacs style
+/// editing keys
+#[derive(Debug)]
+pub struct Editor {
+    /// The line at the prompt, None while no command is being read
+    line: Mutex<Option<Line>>,
+    /// Text removed by the last kill, put back by Ctrl-Y
+    killed: Mutex<String>,
+    /// Completes the text before the cursor when Tab is pressed
+    complete: fn(&str) -> Completions,
+}
+
+/// A command line being edited
+#[derive(Debug)]
+struct Line {
+    prompt: String,
+    buf: String,
+    /// Byte offset of the cursor in buf
+    pos: usize,
+    /// Row the cursor is on, counting from the prompt's row
+    row: usize,
+    /// Earlier lines, Up and Down go through them
+    history: Vec<String>,
+    /// The history entry shown, one past the last while on the new line
+    index: usize,
+    /// The new line, kept while going through the history
+    edited: String,
+    search: Option<Search>,
+}
+
+/// An incremental history search started by Ctrl-R or Ctrl-S, which shows
+/// the entry it finds on the line and what it looks for below it
+#[derive(Debug)]
+struct Search {
+    query: String,
+    /// Ctrl-S looks at newer entries, Ctrl-R at older ones
+    forward: bool,
+    /// No entry contains the query, so the entry found has its characters
+    /// in order, ignoring case
+    fuzzy: bool,
+    failed: bool,
+    /// The entry shown, one past the last before anything is found
+    at: usize,
+    /// Where the search started and the line and cursor there, which Ctrl-G
+    /// goes back to
+    start: (usize, String, usize),
+}
+
+/// A key press, decoded from the bytes the terminal sends for it
+#[derive(Debug, PartialEq)]
+enum Key {
+    Char(char),
+    /// A control character, by the letter typed with Ctrl
+    Ctrl(char),
+    Backspace,
+    Delete,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    WordLeft,
+    WordRight,
+    /// Alt-D
+    KillWordRight,
+    /// Alt-Backspace
+    KillWordLeft,
+    Unknown,
+}
+
+impl Editor {
+    pub const fn new(complete: fn(&str) -> Completions) -> Self {
+        Editor {
+            line: Mutex::new(None),
+            killed: Mutex::new(String::new()),
+            complete,
+        }
+    }
+
+    /// Shows prompt and appends the line typed after it to buf, with its
+    /// newline like `BufRead::read_line`. Reads nothing at end of input.
+    pub fn read_line(
+        &self,
+        prompt: &str,
+        history: Vec<String>,
+        buf: &mut String,
+    ) -> io::Result<usize> {
+        let modes = tcgetattr(stdin())?;
+        let mut raw = modes.clone();
+        raw.local_flags
+            .remove(LocalFlags::ICANON | LocalFlags::ECHO | LocalFlags::ISIG | LocalFlags::IEXTEN);
+        raw.input_flags.remove(InputFlags::ICRNL | InputFlags::IXON);
+        raw.control_chars[SpecialCharacterIndices::VMIN as usize] = 1;
+        raw.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
+        tcsetattr(stdin(), SetArg::TCSADRAIN, &raw)?;
+
+        let mut line = Line {
+            prompt: prompt.to_string(),
+            buf: String::new(),
+            pos: 0,
+            row: 0,
+            index: history.len(),
+            history,
+            edited: String::new(),
+            search: None,
+        };
+        line.render();
+        *self.line.lock().unwrap() = Some(line);
+        let res = self.edit();
+        *self.line.lock().unwrap() = None;
+        tcsetattr(stdin(), SetArg::TCSADRAIN, &modes)?;
+        let line = res?;
+        buf.push_str(&line);
+        Ok(line.len())
+    }
+
+    /// Prints text on lines of its own above the line being edited, which is
+    /// drawn again below it. Without one this is just `println!`.
+    pub fn print(&self, text: &str) {
+        let mut line = self.line.lock().unwrap();
+        match line.as_mut() {
+            Some(line) => {
+                line.clear();
+                write(&format!("{}\n", text));
+                line.render();
+            }
+            None => println!("{}", text),
+        }
+    }
+
+    /// Handles keys until the line is accepted or input ends
+    fn edit(&self) -> io::Result<String> {
+        loop {
+            let key = read_key()?;
+            if key == Some(Key::Ctrl('i')) {
+                self.tab();
+                continue;
+            }
+            let mut guard = self.line.lock().unwrap();
+            let line = guard.as_mut().unwrap();
+            if let Some(key) = &key {
+                if line.search(key) {
+                    line.render();
+                    continue;
+                }
+            }
+            match key {
+                // a partial line before the end of input still runs
+                None => {
+                    write("\n");
+                    return Ok(line.buf.clone());
+                }
+                Some(Key::Ctrl('d')) if line.buf.is_empty() => {
+                    write("\n");
+                    return Ok(String::new());
+                }
+                Some(Key::Ctrl('j' | 'm')) => {
+                    line.pos = line.buf.len();
+                    line.render();
+                    write("\n");
+                    return Ok(format!("{}\n", line.buf));
+                }
+                Some(Key::Ctrl('c')) => {
+                    line.pos = line.buf.len();
+                    line.render();
+                    write("^C\n");
+                    line.buf.clear();
+                    line.pos = 0;
+                    line.row = 0;
+                }
+                Some(Key::Ctrl('l')) => {
+                    write("\x1b[H\x1b[2J");
+                    line.row = 0;
+                }
+                Some(Key::Char(c)) => {
+                    line.buf.insert(line.pos, c);
+                    line.pos += c.len_utf8();
+                }
+                Some(Key::Ctrl('p') | Key::Up) if line.index > 0 => line.show(line.index - 1),
+                Some(Key::Ctrl('n') | Key::Down) if line.index < line.history.len() => {
+                    line.show(line.index + 1)
+                }
+                Some(Key::Ctrl(c @ ('r' | 's'))) => {
+                    line.search = Some(Search {
+                        query: String::new(),
+                        forward: c == 's',
+                        fuzzy: false,
+                        failed: false,
+                        at: line.index,
+                        start: (line.index, line.buf.clone(), line.pos),
+                    })
+                }
+                Some(Key::Ctrl('a') | Key::Home) => line.pos = 0,
+                Some(Key::Ctrl('e') | Key::End) => line.pos = line.buf.len(),
+                Some(Key::Ctrl('b') | Key::Left) => line.pos = line.prev(line.pos),
+                Some(Key::Ctrl('f') | Key::Right) => line.pos = line.next(line.pos),
+                Some(Key::WordLeft) => line.pos = line.word_start(),
+                Some(Key::WordRight) => line.pos = line.word_end(),
+                Some(Key::Backspace) => {
+                    line.kill(line.prev(line.pos));
+                }
+                Some(Key::Ctrl('d') | Key::Delete) => {
+                    line.kill(line.next(line.pos));
+                }
+                Some(Key::Ctrl('k')) => *self.killed.lock().unwrap() = line.kill(line.buf.len()),
+                Some(Key::Ctrl('u')) => *self.killed.lock().unwrap() = line.kill(0),
+                Some(Key::Ctrl('w')) => {
+                    let start = line.buf[..line.pos]
+                        .trim_end()
+                        .rfind(' ')
+                        .map_or(0, |i| i + 1);
+                    *self.killed.lock().unwrap() = line.kill(start);
+                }
+                Some(Key::KillWordLeft) => {
+                    *self.killed.lock().unwrap() = line.kill(line.word_start());
+                }
+                Some(Key::KillWordRight) => {
+                    *self.killed.lock().unwrap() = line.kill(line.word_end());
+                }
+                Some(Key::Ctrl('y')) => {
+                    let killed = self.killed.lock().unwrap();
+                    line.buf.insert_str(line.pos, &killed);
+                    line.pos += killed.len();
+                }
+                Some(Key::Ctrl('t')) if line.pos > 0 && line.buf.len() > 1 => {
+                    // swaps the characters around the cursor, or the last two
+                    let pos = match line.pos == line.buf.len() {
+                        true => line.prev(line.pos),
+                        false => line.pos,
+                    };
+                    let start = line.prev(pos);
+                    let end = line.next(pos);
+                    let swapped = format!("{}{}", &line.buf[pos..end], &line.buf[start..pos]);
+                    line.buf.replace_range(start..end, &swapped);
+                    line.pos = end;
+                }
+                Some(_) => continue,
+            }
+            line.render();
+        }
+    }
+
+    /// Completes the word at the cursor. With several candidates what they
+    /// start with is filled in, and when that adds nothing they are listed.
+    fn tab(&self) {
+        let before = match self.line.lock().unwrap().as_mut() {
+            Some(line) if line.search.is_none() => line.buf[..line.pos].to_string(),
+            _ => return,
+        };
+        // completing looks at the jobs, whose lock the event loop holds when
+        // it prints through the editor
+        let Completions { start, candidates } = (self.complete)(&before);
+        let mut guard = self.line.lock().unwrap();
+        let line = guard.as_mut().unwrap();
+        let text = match &candidates[..] {
+            [] => {
+                write("\x07");
+                return;
+            }
+            [candidate] => match candidate.partial {
+                true => candidate.text.clone(),
+                false => format!("{} ", candidate.text),
+            },
+            [first, rest @ ..] => {
+                let mut common = first.text.as_str();
+                for candidate in rest {
+                    let len = common
+                        .char_indices()
+                        .zip(candidate.text.chars())
+                        .find(|((_, a), b)| a != b)
+                        .map_or(common.len().min(candidate.text.len()), |((i, _), _)| i);
+                    common = &common[..len];
+                }
+                if common.len() <= line.pos - start {
+                    line.clear();
+                    write(&list(&candidates));
+                    line.render();
+                    return;
+                }
+                common.to_string()
+            }
+        };
+        line.buf.replace_range(start..line.pos, &text);
+        line.pos = start + text.len();
+        line.render();
+    }
+}
+
+impl Line {
+    /// Handles a key during a search, returns false if it ends the search
+    /// and is to be handled as usual. Enter takes the entry found for
+    /// editing, Ctrl-G goes back to the line the search started from.
+    fn search(&mut self, key: &Key) -> bool {
+        let Some(search) = self.search.as_mut() else {
+            return false;
+        };
+        match key {
+            Key::Char(c) => {
+                search.query.push(*c);
+                self.find(false);
+            }
+            Key::Backspace => {
+                search.query.pop();
+                search.at = search.start.0;
+                match search.query.is_empty() {
+                    true => {
+                        (self.buf, self.pos) = (search.start.1.clone(), search.start.2);
+                        search.failed = false;
+                    }
+                    false => self.find(false),
+                }
+            }
+            Key::Ctrl(c @ ('r' | 's')) => {
+                search.forward = *c == 's';
+                self.find(true);
+            }
+            Key::Ctrl('g' | 'c') => {
+                (self.buf, self.pos) = (search.start.1.clone(), search.start.2);
+                self.search = None;
+            }
+            Key::Ctrl('j' | 'm') => self.accept(),
+            _ => {
+                self.accept();
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Ends the search on the entry it found
+    fn accept(&mut self) {
+        if let Some(search) = self.search.take() {
+            if search.at < self.history.len() && self.index == self.history.len() {
+                self.edited = search.start.1;
+            }
+            self.index = search.at.min(self.history.len());
+        }
+    }
+
+    /// Shows the next entry the query matches, looking past the one shown if
+    /// skip is set. Entries containing the query come before fuzzy matches.
+    fn find(&mut self, skip: bool) {
+        let search = self.search.as_mut().unwrap();
+        let (at, len) = (search.at, self.history.len());
+        let candidates: Vec<usize> = match (search.forward, skip) {
+            (false, true) => (0..at).rev().collect(),
+            (false, false) => (0..(at + 1).min(len)).rev().collect(),
+            (true, true) => (at + 1..len).collect(),
+            (true, false) => (at..len).collect(),
+        };
+        for fuzzy in [false, true] {
+            for &i in &candidates {
+                let entry = &self.history[i];
+                if skip && *entry == self.buf {
+                    continue;
+                }
+                if let Some(pos) = find(entry, &search.query, fuzzy) {
+                    (search.at, search.fuzzy, search.failed) = (i, fuzzy, false);
+                    (self.buf, self.pos) = (entry.clone(), pos);
+                    return;
+                }
+            }
+        }
+        search.failed = true;
+    }
+
+    /// Replaces the line with history entry index, or the new line past the
+    /// last entry, with the cursor at its end
+    fn show(&mut self, index: usize) {
+        if self.index == self.history.len() {
+            self.edited = self.buf.clone();
+        }
+        self.buf = match self.history.get(index) {
+            Some(entry) => entry.clone(),
+            None => self.edited.clone(),
+        };
+        self.index = index;
+        self.pos = self.buf.len();
+    }
+
+    /// Removes the text between the cursor and to, leaving the cursor at
+    /// its start, and returns it
+    fn kill(&mut self, to: usize) -> String {
+        let range = self.pos.min(to)..self.pos.max(to);
+        self.pos = range.start;
+        self.buf.drain(range).collect()
+    }
+
+    /// The start of the character before pos, together with the combining
+    /// marks that follow it
+    fn prev(&self, pos: usize) -> usize {
+        let mut chars = self.buf[..pos].char_indices().rev();
+        match chars.find(|(_, c)| c.width() != Some(0)) {
+            Some((i, _)) => i,
+            None => 0,
+        }
+    }
+
+    /// The end of the character at pos, together with the combining marks
+    /// that follow it
+    fn next(&self, pos: usize) -> usize {
+        let mut chars = self.buf[pos..].char_indices().skip(1);
+        match chars.find(|(_, c)| c.width() != Some(0)) {
+            Some((i, _)) => pos + i,
+            None => self.buf.len(),
+        }
+    }
+
+    /// The start of the word before the cursor, words being letters and digits
+    fn word_start(&self) -> usize {
+        let before = self.buf[..self.pos].trim_end_matches(|c: char| !c.is_alphanumeric());
+        match before.rfind(|c: char| !c.is_alphanumeric()) {
+            Some(i) => i + before[i..].chars().next().map_or(1, char::len_utf8),
+            None => 0,
+        }
+    }
+
+    /// The end of the word after the cursor
+    fn word_end(&self) -> usize {
+        let after = &self.buf[self.pos..];
+        let start = after.len()
+            - after
+                .trim_start_matches(|c: char| !c.is_alphanumeric())
+                .len();
+        match after[start..].find(|c: char| !c.is_alphanumeric()) {
+            Some(i) => self.pos + start + i,
+            None => self.buf.len(),
+        }
+    }
+
+    /// Moves the cursor back to the start of the prompt and erases the line
+    fn clear(&mut self) {
+        let mut seq = String::new();
+        if self.row > 0 {
+            seq.push_str(&format!("\x1b[{}A", self.row));
+        }
+        seq.push_str("\r\x1b[J");
+        write(&seq);
+        self.row = 0;
+    }
+
+    /// Draws the prompt and the line over the old one and places the cursor
+    fn render(&mut self) {
+        let cols = columns();
+        let mut seq = String::new();
+        if self.row > 0 {
+            seq.push_str(&format!("\x1b[{}A", self.row));
+        }
+        seq.push_str("\r\x1b[J");
+        seq.push_str(&self.prompt.replace(['\x01', '\x02'], ""));
+        seq.push_str(&self.buf);
+
+        let start = advance((0, 0), &self.prompt, cols);
+        let mut end = advance(start, &self.buf, cols);
+        if let Some(search) = &self.search {
+            let status = search.to_string();
+            seq.push('\n');
+            seq.push_str(&status);
+            end = advance((end.0 + 1, 0), &status, cols);
+        }
+        let cursor = advance(start, &self.buf[..self.pos], cols);
+        // a wide character that doesn't fit is drawn on the next row
+        let cursor = match self.buf[self.pos..].chars().next() {
+            Some(c) if cursor.1 + c.width().unwrap_or(0) > cols => (cursor.0 + 1, 0),
+            _ => cursor,
+        };
+        let wrap = |(row, col): (usize, usize)| match col >= cols {
+            true => (row + 1, 0),
+            false => (row, col),
+        };
+        let (end, (row, col)) = (wrap(end), wrap(cursor));
+        // the terminal holds the cursor at the edge until something follows
+        if end.1 == 0 && end.0 > 0 {
+            seq.push_str("\r\n");
+        }
+        if end.0 > row {
+            seq.push_str(&format!("\x1b[{}A", end.0 - row));
+        }
+        seq.push('\r');
+        if col > 0 {
+            seq.push_str(&format!("\x1b[{}C", col));
+        }
+        write(&seq);
+        self.row = row;
+    }
+}
+
+impl Display for Search {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "({}{}{}-search)`{}'",
+            ["", "failed "][self.failed as usize],
+            ["reverse-", ""][self.forward as usize],
+            ["i", "fuzzy"][self.fuzzy as usize],
+            self.query
+        )
+    }
+}
+
+/// The candidates in columns, or one per line with their descriptions
+fn list(candidates: &[Candidate]) -> String {
+    if candidates
+        .iter()
+        .any(|candidate| candidate.description.is_some())
+    {
+        let width = candidates.iter().map(|c| c.name.width()).max().unwrap_or(0);
+        return candidates
+            .iter()
+            .map(|candidate| match &candidate.description {
+                Some(description) => format!("{:<width$}  {}\n", candidate.name, description),
+                None => format!("{}\n", candidate.name),
+            })
+            .collect();
+    }
+    let width = candidates.iter().map(|c| c.name.width()).max().unwrap_or(0) + 2;
+    let per_row = (columns() / width).max(1);
+    let rows = candidates.len().div_ceil(per_row);
+    let mut res = String::new();
+    for row in 0..rows {
+        let mut line = String::new();
+        for candidate in candidates.iter().skip(row).step_by(rows) {
+            line.push_str(&candidate.name);
+            line.push_str(&" ".repeat(width - candidate.name.width()));
+        }
+        res.push_str(line.trim_end());
+        res.push('\n');
+    }
+    res
+}
+
+/// Where query is in entry, or with fuzzy where its first character is
+/// when all of them are there in order
+fn find(entry: &str, query: &str, fuzzy: bool) -> Option<usize> {
+    if !fuzzy {
+        return entry.find(query);
+    }
+    let mut query = query.chars().flat_map(char::to_lowercase).peekable();
+    let mut first = None;
+    for (i, c) in entry.char_indices() {
+        match query.peek() {
+            Some(&next) if c.to_lowercase().eq([next]) => {
+                first.get_or_insert(i);
+                query.next();
+            }
+            Some(_) => {}
+            None => break,
+        }
+    }
+    match query.peek() {
+        None => first.or(Some(0)),
+        Some(_) => None,
+    }
+}
+
+/// Where the cursor is after writing text from (row, col) on a terminal
+/// cols wide. Escape sequences and whatever a prompt puts between `\x01`
+/// and `\x02` take no room.
+fn advance((mut row, mut col): (usize, usize), text: &str, cols: usize) -> (usize, usize) {
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x01' => _ = chars.by_ref().find(|&c| c == '\x02'),
+            '\n' => (row, col) = (row + 1, 0),
+            '\x1b' => match chars.next() {
+                Some('[') => _ = chars.by_ref().find(|c| ('@'..='~').contains(c)),
+                Some(']') => _ = chars.by_ref().find(|&c| c == '\x07'),
+                _ => {}
+            },
+            c => {
+                let width = c.width().unwrap_or(0);
+                (row, col) = match col + width > cols {
+                    true => (row + 1, width),
+                    false => (row, col + width),
+                };
+            }
+        }
+    }
+    (row, col)
+}
+
+/// The width of the terminal
+fn columns() -> usize {
+    let mut size = unsafe { std::mem::zeroed::<libc::winsize>() };
+    match unsafe { libc::ioctl(STDIN_FILENO, libc::TIOCGWINSZ, &mut size) } {
+        0 if size.ws_col > 0 => size.ws_col as usize,
+        _ => 80,
+    }
+}
+
+fn write(seq: &str) {
+    let mut out = stdout().lock();
+    let _ = out.write_all(seq.as_bytes());
+    let _ = out.flush();
+}
+
+fn read_byte() -> io::Result<Option<u8>> {
+    let mut byte = [0u8];
+    loop {
+        match read(STDIN_FILENO, &mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(Errno::EINTR) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Reads one key press, None at end of input
+fn read_key() -> io::Result<Option<Key>> {
+    let byte = match read_byte()? {
+        Some(byte) => byte,
+        None => return Ok(None),
+    };
+    let key = match byte {
+        0x1b => escape()?,
+        0x08 | 0x7f => Key::Backspace,
+        0x00..=0x1f => Key::Ctrl((byte + b'`') as char),
+        _ => {
+            // the rest of a UTF-8 sequence
+            let len = match byte {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                match read_byte()? {
+                    Some(byte) => bytes.push(byte),
+                    None => break,
+                }
+            }
+            match std::str::from_utf8(&bytes)
+                .ok()
+                .and_then(|s| s.chars().next())
+            {
+                Some(c) => Key::Char(c),
+                None => Key::Unknown,
+            }
+        }
+    };
+    Ok(Some(key))
+}
+
+/// Decodes what follows an escape, an Alt key or a CSI sequence
+fn escape() -> io::Result<Key> {
+    let byte = read_byte()?.unwrap_or(0);
+    if byte != b'[' && byte != b'O' {
+        return Ok(match byte {
+            b'b' => Key::WordLeft,
+            b'f' => Key::WordRight,
+            b'd' => Key::KillWordRight,
+            0x08 | 0x7f => Key::KillWordLeft,
+            _ => Key::Unknown,
+        });
+    }
+    let mut params = String::new();
+    let last = loop {
+        match read_byte()? {
+            Some(byte @ 0x40..=0x7e) => break byte,
+            Some(byte) => params.push(byte as char),
+            None => return Ok(Key::Unknown),
+        }
+    };
+    Ok(match (params.as_str(), last) {
+        ("", b'A') => Key::Up,
+        ("", b'B') => Key::Down,
+        ("", b'D') => Key::Left,
+        ("", b'C') => Key::Right,
+        ("1;3" | "1;5", b'D') => Key::WordLeft,
+        ("1;3" | "1;5", b'C') => Key::WordRight,
+        ("", b'H') | ("1" | "7", b'~') => Key::Home,
+        ("", b'F') | ("4" | "8", b'~') => Key::End,
+        ("3", b'~') => Key::Delete,
+        _ => Key::Unknown,
+    })
+}
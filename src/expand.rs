@@ -0,0 +1,126 @@
+use nix::unistd::{getuid, User};
+
+use crate::{
+    ast::{self, Word, WordPart},
+    redirect::Redirect,
+    vars::Variables,
+};
+
+/// A command after expansion, ready to be run
+#[derive(Debug)]
+pub struct Expanded {
+    pub assignments: Vec<(String, String)>,
+    pub argv: Vec<String>,
+    pub redirects: Vec<Redirect>,
+}
+
+pub fn command(command: &ast::Command, vars: &Variables, status: i32) -> Expanded {
+    let word = |w: &Word| word(w, vars, status);
+    Expanded {
+        assignments: command
+            .assignments
+            .iter()
+            .map(|(name, value)| (name.clone(), word(value)))
+            .collect(),
+        argv: command
+            .words
+            .iter()
+            .flat_map(|w| fields(w, vars, status))
+            .collect(),
+        redirects: command
+            .redirects
+            .iter()
+            .map(|redirect| match redirect {
+                ast::Redirect::Read(fd, w) => Redirect::Read(*fd, word(w)),
+                ast::Redirect::Write(fd, w) => Redirect::Write(*fd, word(w)),
+                ast::Redirect::Append(fd, w) => Redirect::Append(*fd, word(w)),
+                ast::Redirect::WriteAll(w) => Redirect::WriteAll(word(w)),
+                ast::Redirect::Dup(fd, target) => Redirect::Dup(*fd, *target),
+            })
+            .collect(),
+    }
+}
+
+pub fn word(word: &Word, vars: &Variables, status: i32) -> String {
+    word.parts
+        .iter()
+        .map(|part| self::part(part, vars, status))
+        .collect()
+}
+
+/// Expands a command argument. `$@` turns into one field per positional
+/// parameter, the text around it sticking to the first and last one. A word
+/// of only unquoted expansions that are all empty leaves no field.
+pub fn fields(word: &Word, vars: &Variables, status: i32) -> Vec<String> {
+    let is_at = |part: &WordPart| matches!(part, WordPart::Param(param) if param.name == "@");
+    if !word.parts.iter().any(is_at) {
+        let field = self::word(word, vars, status);
+        let unquoted = |part: &WordPart| matches!(part, WordPart::Param(param) if !param.quoted);
+        if field.is_empty() && word.parts.iter().all(unquoted) {
+            return vec![];
+        }
+        return vec![field];
+    }
+    let mut fields = vec![];
+    let mut current = String::new();
+    for part in &word.parts {
+        if !is_at(part) {
+            current += &self::part(part, vars, status);
+            continue;
+        }
+        if let Some((last, first)) = vars.positional().split_last() {
+            for arg in first {
+                current += arg;
+                fields.push(std::mem::take(&mut current));
+            }
+            current += last;
+        }
+    }
+    // a lone `$@` without positional parameters leaves no field at all
+    if !fields.is_empty() || !current.is_empty() || !word.parts.iter().all(is_at) {
+        fields.push(current);
+    }
+    fields
+}
+
+fn part(part: &WordPart, vars: &Variables, status: i32) -> String {
+    match part {
+        WordPart::Literal(literal) => literal.clone(),
+        WordPart::Tilde(user) => home(user, vars),
+        WordPart::Param(param) => match (value(&param.name, vars, status), &param.default) {
+            (Some(value), _) if !value.is_empty() || param.default.is_none() => value,
+            (_, Some(default)) => word(default, vars, status),
+            _ => String::new(),
+        },
+    }
+}
+
+/// The value of a variable or special parameter, `None` if it is unset
+fn value(name: &str, vars: &Variables, status: i32) -> Option<String> {
+    match name {
+        "?" => Some(status.to_string()),
+        "#" => Some(vars.positional().len().to_string()),
+        "!" => vars.last_background().map(|pid| pid.to_string()),
+        "@" => Some(vars.positional().join(" ")),
+        name if name.starts_with(|c: char| c.is_ascii_digit()) => {
+            vars.arg(name.parse().ok()?).map(|arg| arg.to_string())
+        }
+        name => vars.get(name).map(|value| value.to_string()),
+    }
+}
+
+/// The home directory `~user` stands for, `~` alone is `$HOME`. Unknown
+/// users are left as they were typed.
+fn home(user: &str, vars: &Variables) -> String {
+    let entry = match user {
+        "" => match vars.get("HOME") {
+            Some(home) => return home.to_string(),
+            None => User::from_uid(getuid()),
+        },
+        user => User::from_name(user),
+    };
+    match entry {
+        Ok(Some(entry)) => entry.dir.display().to_string(),
+        _ => format!("~{}", user),
+    }
+}
@@ -0,0 +1,111 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use nix::unistd::{access, AccessFlags};
+
+/// Remembers where bare command names were found on `$PATH`
+#[derive(Debug)]
+pub struct PathCache {
+    entries: BTreeMap<String, (PathBuf, u32)>,
+}
+
+impl PathCache {
+    pub fn new() -> Self {
+        PathCache {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Resolves a command name the way `execvp` does: names containing a
+    /// slash are used as is, anything else is looked up on paths
+    pub fn lookup(&mut self, name: &str, paths: &str) -> Option<PathBuf> {
+        if name.contains('/') {
+            return Some(PathBuf::from(name));
+        }
+        if let Some((path, hits)) = self.entries.get_mut(name) {
+            if executable(path) {
+                *hits += 1;
+                return Some(path.clone());
+            }
+        }
+        let path = search(name, paths)?;
+        self.entries.insert(name.to_string(), (path.clone(), 1));
+        Some(path)
+    }
+
+    /// Adds name to the table without counting it as a use
+    pub fn add(&mut self, name: &str, paths: &str) -> bool {
+        match search(name, paths) {
+            Some(path) => {
+                self.entries.insert(name.to_string(), (path, 0));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PathBuf> {
+        self.entries.get(name).map(|(path, _)| path)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn list(&self) -> String {
+        if self.entries.is_empty() {
+            return "hash: hash table empty\n".to_string();
+        }
+        let mut res = "hits\tcommand\n".to_string();
+        for (path, hits) in self.entries.values() {
+            res += &format!("{:4}\t{}\n", hits, path.display());
+        }
+        res
+    }
+}
+
+/// Searches every directory of paths, a `$PATH` value, for an executable
+/// called name
+pub fn search(name: &str, paths: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        let path = PathBuf::from(name);
+        return executable(&path).then_some(path);
+    }
+    paths
+        .split(':')
+        .map(|dir| match dir {
+            // an empty entry means the current directory
+            "" => Path::new(".").join(name),
+            dir => Path::new(dir).join(name),
+        })
+        .find(|path| executable(path))
+}
+
+/// The names of the executables on paths that start with prefix
+pub fn commands(prefix: &str, paths: &str) -> Vec<String> {
+    let mut names = vec![];
+    for dir in paths
+        .split(':')
+        .map(|dir| if dir.is_empty() { "." } else { dir })
+    {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            match entry.file_name().into_string() {
+                Ok(name) if name.starts_with(prefix) && executable(&entry.path()) => {
+                    names.push(name)
+                }
+                _ => {}
+            }
+        }
+    }
+    names
+}
+
+fn executable(path: &Path) -> bool {
+    path.is_file() && access(path, AccessFlags::X_OK).is_ok()
+}
@@ -2,13 +2,7 @@ use nix::sys::{
     signal::{sigaction, SaFlags, SigAction, SigHandler, Signal},
     signalfd::SigSet,
 };
-use regex::Regex;
-use std::{os::raw::c_int, process::exit};
-
-pub extern "C" fn sigquit_handler(_sigquit: i32) {
-    println!("Terminating after receipt of SIGQUIT signal");
-    exit(0)
-}
+use std::{os::raw::c_int, process::exit, str::FromStr};
 
 pub fn unix_error(msg: &str) -> ! {
     println!("{}: {}", msg, dbg!(nix::errno::Errno::last()));
@@ -22,35 +16,81 @@ pub fn app_error(msg: &str) -> ! {
 }
 
 pub fn usage() {
-    println!("Usage: shell [-hvp]");
+    println!("Usage: shell [-hvp] [-c command | script] [args...]");
     println!("\t-h   print this message");
     println!("\t-v   print additional diagnostic information");
-    println!("\t-p   do not emit a command prompt")
+    println!("\t-p   do not emit a command prompt");
+    println!("\t-c   run command instead of reading stdin")
 }
 
-pub fn parse_line(line: &str) -> (Vec<String>, bool) {
-    let re = Regex::new(r#""([^"]*)"|'([^']*)'|\S+"#).unwrap();
-    let mut argv: Vec<String> = re
-        .captures_iter(line)
-        .filter_map(|cap| {
-            cap.get(1)
-                .or(cap.get(2))
-                .or(cap.get(0))
-                .map(|m| m.as_str().to_string())
-        })
-        .collect();
+/// What the command line asks the shell to do
+#[derive(Debug, Default)]
+pub struct Options {
+    pub help: bool,
+    pub verbose: bool,
+    pub no_prompt: bool,
+    /// The string given to `-c`
+    pub command: Option<String>,
+    /// The script to run, when not reading commands from `-c` or stdin
+    pub script: Option<String>,
+    /// `$0` followed by the positional parameters
+    pub args: Vec<String>,
+}
 
-    let background = if let Some(last) = argv.last() {
-        last == "&"
-    } else {
-        false
-    };
+impl Options {
+    /// Commands come from stdin, so the shell is interactive
+    pub fn interactive(&self) -> bool {
+        self.command.is_none() && self.script.is_none()
+    }
+}
 
-    if background {
-        argv.pop();
+/// Parses `tsh [-hvp] [-c command [name [args...]] | script [args...]]`.
+/// Options stop at the first operand, so a script's own flags are left to it.
+pub fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    let name = args.next().unwrap_or_else(|| "tsh".to_string());
+    let mut command = false;
+    let mut rest: Vec<String> = vec![];
+    for arg in args.by_ref() {
+        match arg.as_str() {
+            "--" => break,
+            "--help" => options.help = true,
+            "--verbose" => options.verbose = true,
+            "--prompt" => options.no_prompt = true,
+            flags if flags.starts_with('-') && flags.len() > 1 => {
+                for flag in flags[1..].chars() {
+                    match flag {
+                        'h' => options.help = true,
+                        'v' => options.verbose = true,
+                        'p' => options.no_prompt = true,
+                        'c' => command = true,
+                        _ => return Err(format!("-{}: invalid option", flag)),
+                    }
+                }
+            }
+            _ => {
+                rest.push(arg);
+                break;
+            }
+        }
     }
+    rest.extend(args);
 
-    (argv, background)
+    if command {
+        if rest.is_empty() {
+            return Err("-c: option requires an argument".to_string());
+        }
+        options.command = Some(rest.remove(0));
+        if rest.is_empty() {
+            rest.push(name);
+        }
+    } else if let Some(script) = rest.first() {
+        options.script = Some(script.clone());
+    } else {
+        rest.push(name);
+    }
+    options.args = rest;
+    Ok(options)
 }
 
 pub unsafe fn set_handler(
@@ -62,3 +102,33 @@ pub unsafe fn set_handler(
     let action = SigAction::new(SigHandler::Handler(handler), SaFlags::SA_RESTART, mask);
     sigaction(sig, &action)
 }
+
+/// Parses a signal number, or a name with or without `SIG` in any case.
+/// `Some(None)` is signal 0, which only checks that the target exists.
+pub fn parse_signal(spec: &str) -> Option<Option<Signal>> {
+    if let Ok(number) = spec.parse::<i32>() {
+        return match number {
+            0 => Some(None),
+            number => Signal::try_from(number).ok().map(Some),
+        };
+    }
+    let name = spec.to_ascii_uppercase();
+    let name = match name.starts_with("SIG") {
+        true => name,
+        false => format!("SIG{}", name),
+    };
+    Signal::from_str(&name).ok().map(Some)
+}
+
+/// The name of sig without its `SIG` prefix, as `kill -l` prints it
+pub fn signal_name(sig: Signal) -> &'static str {
+    &sig.as_str()[3..]
+}
+
+/// Every signal as `N) NAME`, for `kill -l`
+pub fn signal_list() -> String {
+    Signal::iterator()
+        .map(|sig| format!("{:2}) {}", sig as i32, signal_name(sig)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
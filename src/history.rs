@@ -0,0 +1,294 @@
+use std::{fs, path::PathBuf};
+
+use crate::{
+    lexer::{Lexer, TokenKind},
+    vars::Variables,
+};
+
+/// Lines entered at an interactive shell, the oldest first
+#[derive(Debug)]
+pub struct History {
+    entries: Vec<String>,
+    /// Number of the first entry, which goes up as old entries are dropped
+    base: usize,
+    /// How many of the last entries were entered in this session and are
+    /// still to be saved
+    new: usize,
+}
+
+impl History {
+    pub fn new() -> Self {
+        History {
+            entries: vec![],
+            base: 1,
+            new: 0,
+        }
+    }
+
+    /// Reads the history file, keeping the last `$HISTSIZE` lines
+    pub fn load(&mut self, vars: &Variables) {
+        if let Some(Ok(text)) = file(vars).map(fs::read_to_string) {
+            self.entries.extend(text.lines().map(unescape));
+            self.trim(size(vars, "HISTSIZE"));
+        }
+    }
+
+    /// Appends the lines of this session to the history file, which keeps
+    /// its last `$HISTFILESIZE` lines
+    pub fn save(&mut self, vars: &Variables) {
+        let Some(path) = file(vars) else {
+            return;
+        };
+        let mut lines: Vec<String> = match fs::read_to_string(&path) {
+            Ok(text) => text.lines().map(str::to_string).collect(),
+            Err(_) => vec![],
+        };
+        let new = &self.entries[self.entries.len() - self.new..];
+        lines.extend(new.iter().map(|entry| escape(entry)));
+        let limit = match vars.get("HISTFILESIZE") {
+            Some(_) => size(vars, "HISTFILESIZE"),
+            None => size(vars, "HISTSIZE"),
+        };
+        let keep = &lines[lines.len().saturating_sub(limit)..];
+        let text: String = keep.iter().map(|line| format!("{}\n", line)).collect();
+        if fs::write(&path, text).is_ok() {
+            self.new = 0;
+        }
+    }
+
+    /// Records a line as `$HISTCONTROL` allows: `ignorespace` skips lines
+    /// starting with a space, `ignoredups` repeats of the last line and
+    /// `erasedups` removes earlier copies. A command continued over several
+    /// lines is kept as one entry, newlines included.
+    pub fn add(&mut self, vars: &Variables, line: &str) {
+        let line = line.trim_end_matches('\n');
+        if line.trim().is_empty() {
+            return;
+        }
+        let control = vars.get("HISTCONTROL").unwrap_or_default();
+        let control: Vec<&str> = control.split(':').collect();
+        let has = |option: &str| control.contains(&option) || control.contains(&"ignoreboth");
+        if has("ignorespace") && line.starts_with(' ') {
+            return;
+        }
+        if has("ignoredups") && self.entries.last().is_some_and(|last| last == line) {
+            return;
+        }
+        if control.contains(&"erasedups") {
+            let old = self.entries.len() - self.new;
+            let mut i = 0;
+            self.entries.retain(|entry| {
+                i += 1;
+                if entry != line {
+                    return true;
+                }
+                if i > old {
+                    self.new -= 1;
+                }
+                false
+            });
+        }
+        self.entries.push(line.to_string());
+        self.new += 1;
+        self.trim(size(vars, "HISTSIZE"));
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// `history [n]`: the last n entries, all of them by default, numbered
+    pub fn list(&self, n: Option<usize>) -> String {
+        let skip = self.entries.len() - n.unwrap_or(usize::MAX).min(self.entries.len());
+        self.entries
+            .iter()
+            .enumerate()
+            .skip(skip)
+            .map(|(i, entry)| format!("{:5}  {}\n", self.base + i, entry))
+            .collect()
+    }
+
+    /// `history -c`
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.base = 1;
+        self.new = 0;
+    }
+
+    /// `history -d n`: removes entry n, the entries after it move up
+    pub fn delete(&mut self, n: usize) -> Result<(), String> {
+        let i = match n.checked_sub(self.base) {
+            Some(i) if i < self.entries.len() => i,
+            _ => return Err(format!("{}: history position out of range", n)),
+        };
+        self.entries.remove(i);
+        if i >= self.entries.len() + 1 - self.new {
+            self.new -= 1;
+        }
+        Ok(())
+    }
+
+    /// Replaces the history references in line: `!!` is the last line, `!n`
+    /// line n, `!-n` the nth line back, `!prefix` the last line starting
+    /// with prefix and `!$` the last word of the last line. A line starting
+    /// with `^old^new` is the last line with old replaced by new. Returns
+    /// None when nothing was replaced.
+    pub fn expand(&self, line: &str) -> Result<Option<String>, String> {
+        if let Some(rest) = line.strip_prefix('^') {
+            return self.substitute(rest.trim_end_matches('\n')).map(Some);
+        }
+        let mut res = String::new();
+        let (mut single, mut double, mut changed) = (false, false, false);
+        let mut i = 0;
+        while let Some(c) = line[i..].chars().next() {
+            i += c.len_utf8();
+            match c {
+                '\'' if !double => single = !single,
+                '"' if !single => double = !double,
+                // an escaped `!` stays, the backslash goes with quote removal
+                '\\' if !single => {
+                    res.push(c);
+                    if let Some(next) = line[i..].chars().next() {
+                        res.push(next);
+                        i += next.len_utf8();
+                    }
+                    continue;
+                }
+                '!' if !single => {
+                    if let Some((event, len)) = self.event(&line[i..])? {
+                        res.push_str(&event);
+                        i += len;
+                        changed = true;
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            res.push(c);
+        }
+        Ok(changed.then_some(res))
+    }
+
+    /// The text of the history reference at the start of spec, which follows
+    /// a `!`, and how long the reference is. None if the `!` is literal.
+    fn event(&self, spec: &str) -> Result<Option<(String, usize)>, String> {
+        let last = || {
+            self.back(1)
+                .ok_or_else(|| format!("!{}: event not found", &spec[..1]))
+        };
+        if spec.starts_with('!') {
+            return Ok(Some((last()?.to_string(), 1)));
+        }
+        if spec.starts_with('$') {
+            return Ok(Some((last_word(last()?), 1)));
+        }
+        let len = spec
+            .find(|c: char| c.is_whitespace() || "\"';&|<>()".contains(c))
+            .unwrap_or(spec.len());
+        let word = &spec[..len];
+        if word.is_empty() || word.starts_with('=') {
+            return Ok(None);
+        }
+        let entry = match word.strip_prefix('-').unwrap_or(word).parse::<usize>() {
+            Ok(n) if word.starts_with('-') => self.back(n),
+            Ok(n) => n
+                .checked_sub(self.base)
+                .and_then(|i| self.entries.get(i))
+                .map(|entry| entry.as_str()),
+            Err(_) => self
+                .entries
+                .iter()
+                .rev()
+                .find(|entry| entry.starts_with(word))
+                .map(|entry| entry.as_str()),
+        };
+        match entry {
+            Some(entry) => Ok(Some((entry.to_string(), len))),
+            None => Err(format!("!{}: event not found", word)),
+        }
+    }
+
+    /// `^old^new^`: the last line with the first old replaced by new
+    fn substitute(&self, spec: &str) -> Result<String, String> {
+        let mut parts = spec.splitn(3, '^');
+        let old = parts.next().unwrap_or_default();
+        let new = parts.next().unwrap_or_default();
+        let rest = parts.next().unwrap_or_default();
+        match self.back(1) {
+            Some(last) if !old.is_empty() && last.contains(old) => {
+                Ok(format!("{}{}\n", last.replacen(old, new, 1), rest))
+            }
+            _ => Err(format!("^{}: substitution failed", spec)),
+        }
+    }
+
+    /// The nth line back, the line being expanded is not in the history yet
+    fn back(&self, n: usize) -> Option<&str> {
+        let i = self.entries.len().checked_sub(n)?;
+        self.entries.get(i).map(|entry| entry.as_str())
+    }
+
+    fn trim(&mut self, size: usize) {
+        let excess = self.entries.len().saturating_sub(size);
+        self.entries.drain(..excess);
+        self.base += excess;
+        self.new = self.new.min(self.entries.len());
+    }
+}
+
+/// `$HISTFILE`, `~/.tsh_history` when unset. Set to nothing, the history
+/// isn't saved.
+fn file(vars: &Variables) -> Option<PathBuf> {
+    match vars.get("HISTFILE") {
+        Some("") => None,
+        Some(path) => Some(PathBuf::from(path)),
+        None => vars
+            .get("HOME")
+            .map(|home| PathBuf::from(home).join(".tsh_history")),
+    }
+}
+
+/// An entry as a line of the history file, with its newlines as `\n` and
+/// backslashes doubled
+fn escape(entry: &str) -> String {
+    entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+/// An entry read back from a line of the history file
+fn unescape(line: &str) -> String {
+    let mut entry = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.next_if_eq(&'n').is_some() => entry.push('\n'),
+            '\\' if chars.next_if_eq(&'\\').is_some() => entry.push('\\'),
+            c => entry.push(c),
+        }
+    }
+    entry
+}
+
+/// A size limit from a variable, 500 lines when it isn't a number
+fn size(vars: &Variables, name: &str) -> usize {
+    vars.get(name)
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(500)
+}
+
+/// The last word of line as it was typed, quotes included
+fn last_word(line: &str) -> String {
+    let words = Lexer::new(line).tokens().map(|tokens| {
+        tokens
+            .into_iter()
+            .rfind(|token| matches!(token.kind, TokenKind::Word(_)))
+            .map(|token| line[token.span].to_string())
+    });
+    match words {
+        Ok(Some(word)) => word,
+        _ => line
+            .split_whitespace()
+            .last()
+            .unwrap_or_default()
+            .to_string(),
+    }
+}
@@ -1,45 +1,210 @@
-use std::fmt::Display;
+use std::{
+    ffi::CStr,
+    fmt::Display,
+    time::{Duration, Instant},
+};
 
-use nix::unistd::Pid;
+use nix::{libc, sys::termios::Termios, unistd::Pid};
 
 pub trait Jobs {
     fn list(&self) -> String;
-    fn add_job(&mut self, job: Job) -> Result<u32, ()>;
-    fn remove_job(&mut self, pid: Pid) -> Result<(), ()>;
-    fn get_pid(&self, pid: Pid) -> Result<&Job, ()>;
+    fn add_job(&mut self, job: Job) -> Result<u32, JobError>;
+    fn remove_job(&mut self, pid: Pid) -> Result<(), JobError>;
+    fn get_pid(&self, pid: Pid) -> Result<&Job, JobError>;
     #[allow(unused)]
-    fn get_jid(&self, jid: u32) -> Result<&Job, ()>;
-    fn get_pid_mut(&mut self, pid: Pid) -> Result<&mut Job, ()>;
-    fn get_jid_mut(&mut self, jid: u32) -> Result<&mut Job, ()>;
-    fn set_state(&mut self, pid: Pid, state: States) -> Result<&Job, ()>;
+    fn get_jid(&self, jid: u32) -> Result<&Job, JobError>;
+    fn get_pid_mut(&mut self, pid: Pid) -> Result<&mut Job, JobError>;
+    fn get_jid_mut(&mut self, jid: u32) -> Result<&mut Job, JobError>;
+    fn set_state(&mut self, pid: Pid, state: States) -> Result<&Job, JobError>;
     fn set_fg(&mut self, pid: Pid);
     fn current(&mut self) -> Option<Pid>;
     fn next_jid(&mut self) -> u32;
 }
 
+/// Why a job could not be found or added
 #[derive(Debug)]
+pub enum JobError {
+    /// No job has a process with this pid
+    NoSuchPid(Pid),
+    /// No job has this job id
+    NoSuchJid(u32),
+    /// A `%` job spec that matches no job
+    NoSuchJob(String),
+    /// A `%` job spec that matches more than one job
+    Ambiguous(String),
+    /// Neither a pid nor a `%` job spec
+    BadArgument,
+    /// Another job is running in the foreground already
+    ForegroundBusy,
+    /// The job finished and can't be continued
+    Terminated,
+    /// A change of state that can't happen, like a finished job stopping
+    Transition(States, States),
+}
+
+impl Display for JobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoSuchPid(pid) => write!(f, "({}): No such process", pid),
+            Self::NoSuchJid(jid) => write!(f, "%{}: No such job", jid),
+            Self::NoSuchJob(spec) => write!(f, "{}: No such job", spec),
+            Self::Ambiguous(spec) => write!(f, "{}: ambiguous job spec", spec),
+            Self::BadArgument => write!(f, "argument must be a PID or %jobid"),
+            Self::ForegroundBusy => write!(f, "a job is running in the foreground already"),
+            Self::Terminated => write!(f, "job has terminated"),
+            Self::Transition(from, to) => write!(f, "job can't go from {} to {}", from, to),
+        }
+    }
+}
+
+/// Where a job is in its life. A running job can stop, continue in the
+/// foreground or background and finish, a stopped one can continue or be
+/// killed, a finished one stays that way.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum States {
-    FG,
-    BG,
-    ST,
+    Running {
+        fg: bool,
+    },
+    /// Stopped by the signal
+    Stopped(i32),
+    /// The last process exited with the code
+    Exited(i32),
+    /// Killed by the signal, which may have dumped core
+    Signaled(i32, bool),
 }
 
 impl Display for States {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::FG => write!(f, "Foreground"),
-            Self::BG => write!(f, "Running"),
-            Self::ST => write!(f, "Stopped"),
+            Self::Running { fg: true } => write!(f, "Foreground"),
+            Self::Running { fg: false } => write!(f, "Running"),
+            Self::Stopped(_) => write!(f, "Stopped"),
+            Self::Exited(0) => write!(f, "Done"),
+            Self::Exited(code) => write!(f, "Exit {}", code),
+            Self::Signaled(signal, core) => {
+                // the description other shells print, like `Killed`
+                let name = unsafe { CStr::from_ptr(libc::strsignal(*signal)) };
+                write!(f, "{}", name.to_string_lossy())?;
+                match core {
+                    true => write!(f, " (core dumped)"),
+                    false => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl States {
+    pub fn finished(&self) -> bool {
+        matches!(self, Self::Exited(_) | Self::Signaled(..))
+    }
+
+    /// The value `$?` takes once the job finished, signal deaths map to
+    /// 128 + signal number
+    pub fn code(&self) -> Option<i32> {
+        match self {
+            Self::Exited(code) => Some(*code),
+            Self::Signaled(signal, _) => Some(128 + signal),
+            _ => None,
+        }
+    }
+
+    /// Whether a job can go from this state to next
+    fn allows(&self, next: &Self) -> bool {
+        !self.finished() && !matches!((self, next), (Self::Stopped(_), Self::Stopped(_)))
+    }
+}
+
+/// How a process ended
+#[derive(Debug, Clone, Copy)]
+pub enum Status {
+    Exited(i32),
+    /// Killed by the signal, which may have dumped core
+    Signaled(i32, bool),
+}
+
+impl From<Status> for States {
+    fn from(status: Status) -> Self {
+        match status {
+            Status::Exited(code) => Self::Exited(code),
+            Status::Signaled(signal, core) => Self::Signaled(signal, core),
+        }
+    }
+}
+
+/// CPU time and peak memory used by processes that were reaped
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Usage {
+    pub user: Duration,
+    pub system: Duration,
+    /// Largest resident set size of any of the processes, in kilobytes
+    pub maxrss: i64,
+}
+
+impl Usage {
+    /// Adds what one reaped process used
+    pub fn add(&mut self, rusage: &libc::rusage) {
+        self.user += duration(rusage.ru_utime);
+        self.system += duration(rusage.ru_stime);
+        self.maxrss = self.maxrss.max(rusage.ru_maxrss);
+    }
+}
+
+fn duration(time: libc::timeval) -> Duration {
+    Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000)
+}
+
+/// Quotes s as a JSON string
+fn json_string(s: &str) -> String {
+    let mut res = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => res += "\\\"",
+            '\\' => res += "\\\\",
+            '\n' => res += "\\n",
+            '\t' => res += "\\t",
+            c if c.is_control() => res += &format!("\\u{:04x}", c as u32),
+            c => res.push(c),
         }
     }
+    res.push('"');
+    res
+}
+
+/// Formats a duration the way `time` does, like `0m1.250s`
+pub fn minutes(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    format!(
+        "{}m{}.{:03}s",
+        millis / 60_000,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+#[derive(Debug)]
+pub struct Process {
+    pub pid: Pid,
+    pub status: Option<Status>,
 }
 
 #[derive(Debug)]
 pub struct Job {
+    /// Process group id, which is also the pid of the first process
     pub pid: Pid,
     pub jid: u32,
     pub state: States,
     pub cmd: String,
+    pub procs: Vec<Process>,
+    /// Terminal modes saved when the job was stopped in the foreground
+    pub tmodes: Option<Termios>,
+    /// Set by `disown -h`, the job is not sent SIGHUP when the shell exits
+    pub nohup: bool,
+    /// What the processes that finished so far used
+    pub usage: Usage,
+    /// Every state the job was in and when it got there, the first one
+    /// when it started
+    pub history: Vec<(States, Instant)>,
 }
 
 impl Display for Job {
@@ -59,45 +224,89 @@ impl Display for Job {
 pub struct JobManager {
     fg: Option<Pid>,
     jobs: Vec<Job>,
+    /// Off when the shell runs a script, jobs then share its process group
+    control: bool,
+    /// Process group ids ordered by when the job last started, stopped or
+    /// was continued, the most recent last
+    recent: Vec<Pid>,
+    /// Background jobs that finished before anyone waited for them
+    finished: Vec<Finished>,
+    /// Background jobs that finished since the last prompt. They stay in
+    /// `jobs` until they are reported.
+    notices: Vec<Notice>,
+}
+
+/// Tells the user a background job finished, like `[1]+  Done  cmd`
+#[derive(Debug)]
+pub struct Notice {
+    pgid: Pid,
+    jid: u32,
+    /// `+` for the current job, `-` for the previous one
+    marker: char,
+    state: States,
+    cmd: String,
 }
 
+impl Display for Notice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{}]{}  {:<24}{}",
+            self.jid,
+            self.marker,
+            self.state.to_string(),
+            self.cmd.trim().trim_end_matches('&').trim_end()
+        )
+    }
+}
+
+/// The exit status of a finished background job, kept for `wait`
+#[derive(Debug)]
+struct Finished {
+    jid: u32,
+    pids: Vec<Pid>,
+    status: i32,
+}
+
+/// How many finished jobs `wait` can still ask about
+const FINISHED_MAX: usize = 1024;
+
 impl Jobs for JobManager {
-    fn set_state(&mut self, pid: Pid, state: States) -> Result<&Job, ()> {
+    fn set_state(&mut self, pid: Pid, state: States) -> Result<&Job, JobError> {
+        let index = match self.jobs.iter().position(|job| job.has_pid(pid)) {
+            Some(index) => index,
+            None => {
+                return Err(JobError::NoSuchPid(pid));
+            }
+        };
+        let pid = self.jobs[index].pid;
+        self.jobs[index].transition(state)?;
         if let Some(fg) = self.fg {
             if fg == pid {
-                // match state {
-                //     States::ST => self.fg = None,
-                //     _ => {}
-                // };
                 self.fg = None;
             };
         }
-        let index = match self.jobs.iter().position(|job| job.pid == (pid)) {
-            Some(index) => index,
-            None => {
-                return Err(());
-            }
-        };
-        if let States::FG = state {
+        if let States::Running { fg: true } = state {
             self.fg = Some(pid);
         }
-
-        // if States::FG == state {
-        //     self.fg = Some(pid);
-        // }
-        self.jobs[index].state = state;
+        // a job that finishes doesn't become the current one
+        if !state.finished() {
+            self.touch(pid);
+        }
         Ok(&self.jobs[index])
     }
     fn set_fg(&mut self, pid: Pid) {
         self.fg = Some(pid);
+        self.touch(pid);
     }
-    fn remove_job(&mut self, pid: Pid) -> Result<(), ()> {
-        let index = match self.jobs.iter().position(|job| job.pid == (pid)) {
+    fn remove_job(&mut self, pid: Pid) -> Result<(), JobError> {
+        let index = match self.jobs.iter().position(|job| job.has_pid(pid)) {
             Some(index) => index,
             None => {
-                return Err(());
+                return Err(JobError::NoSuchPid(pid));
             }
         };
+        let pid = self.jobs[index].pid;
 
         if let Some(fg) = self.fg {
             if fg == pid {
@@ -106,54 +315,58 @@ impl Jobs for JobManager {
         }
 
         self.jobs.remove(index);
+        self.recent.retain(|&recent| recent != pid);
+        self.notices.retain(|notice| notice.pgid != pid);
         Ok(())
     }
-    fn get_jid(&self, jid: u32) -> Result<&Job, ()> {
+    fn get_jid(&self, jid: u32) -> Result<&Job, JobError> {
         let index = match self.jobs.iter().position(|job| job.jid == jid) {
             Some(index) => index,
             None => {
-                return Err(());
+                return Err(JobError::NoSuchJid(jid));
             }
         };
         Ok(&self.jobs[index])
     }
-    fn get_pid(&self, pid: Pid) -> Result<&Job, ()> {
-        let index = match self.jobs.iter().position(|job| job.pid == pid) {
+    fn get_pid(&self, pid: Pid) -> Result<&Job, JobError> {
+        let index = match self.jobs.iter().position(|job| job.has_pid(pid)) {
             Some(index) => index,
             None => {
-                return Err(());
+                return Err(JobError::NoSuchPid(pid));
             }
         };
         Ok(&self.jobs[index])
     }
-    fn get_jid_mut(&mut self, jid: u32) -> Result<&mut Job, ()> {
+    fn get_jid_mut(&mut self, jid: u32) -> Result<&mut Job, JobError> {
         let index = match self.jobs.iter().position(|job| job.jid == jid) {
             Some(index) => index,
             None => {
-                return Err(());
+                return Err(JobError::NoSuchJid(jid));
             }
         };
         Ok(&mut self.jobs[index])
     }
-    fn get_pid_mut(&mut self, pid: Pid) -> Result<&mut Job, ()> {
-        let index = match self.jobs.iter().position(|job| job.pid == pid) {
+    fn get_pid_mut(&mut self, pid: Pid) -> Result<&mut Job, JobError> {
+        let index = match self.jobs.iter().position(|job| job.has_pid(pid)) {
             Some(index) => index,
             None => {
-                return Err(());
+                return Err(JobError::NoSuchPid(pid));
             }
         };
         Ok(&mut self.jobs[index])
     }
-    fn add_job(&mut self, job: Job) -> Result<u32, ()> {
+    fn add_job(&mut self, job: Job) -> Result<u32, JobError> {
         let jid = self.next_jid();
-        if let States::FG = job.state {
+        if let States::Running { fg: true } = job.state {
             if let Some(_fg) = self.fg {
-                return Err(());
+                return Err(JobError::ForegroundBusy);
             }
+            self.touch(job.pid);
             self.fg = Some(job.pid);
             self.jobs.push(Job { jid, ..job });
             Ok(jid)
         } else {
+            self.touch(job.pid);
             self.fg = None;
             self.jobs.push(Job { jid, ..job });
             Ok(jid)
@@ -182,17 +395,316 @@ impl JobManager {
         JobManager {
             fg: None,
             jobs: vec![],
+            control: true,
+            recent: vec![],
+            finished: vec![],
+            notices: vec![],
         }
     }
+
+    pub fn jobs(&self) -> impl Iterator<Item = &Job> {
+        self.jobs.iter()
+    }
+
+    /// Whether some job is stopped
+    pub fn stopped(&self) -> bool {
+        self.jobs
+            .iter()
+            .any(|job| matches!(job.state, States::Stopped(_)))
+    }
+
+    /// Whether some background job is still running
+    pub fn running(&self) -> bool {
+        self.jobs
+            .iter()
+            .any(|job| matches!(job.state, States::Running { fg: false }))
+    }
+
+    /// Remembers the status of a finished background job until it is waited
+    /// for
+    pub fn record(&mut self, jid: u32, pids: Vec<Pid>, status: i32) {
+        if self.finished.len() == FINISHED_MAX {
+            self.finished.remove(0);
+        }
+        self.finished.push(Finished { jid, pids, status });
+    }
+
+    /// Takes the status of the finished job pid belonged to
+    pub fn take_finished(&mut self, pid: Pid) -> Option<i32> {
+        let index = self
+            .finished
+            .iter()
+            .position(|finished| finished.pids.contains(&pid))?;
+        Some(self.finished.remove(index).status)
+    }
+
+    /// A pid of the finished job with job id jid
+    pub fn finished_pid(&self, jid: u32) -> Option<Pid> {
+        let finished = self.finished.iter().find(|finished| finished.jid == jid)?;
+        finished.pids.first().copied()
+    }
+
+    /// Takes the status of the job that finished first, of the ones pids
+    /// belong to if given
+    pub fn take_any_finished(&mut self, pids: Option<&[Pid]>) -> Option<i32> {
+        let index = self.finished.iter().position(|finished| match pids {
+            Some(pids) => finished.pids.iter().any(|pid| pids.contains(pid)),
+            None => true,
+        })?;
+        Some(self.finished.remove(index).status)
+    }
+
+    pub fn forget_finished(&mut self) {
+        self.finished.clear();
+    }
+
+    /// The notice for the finished job with process group pgid
+    pub fn notice(&self, pgid: Pid) -> Option<Notice> {
+        let job = self.jobs.iter().find(|job| job.pid == pgid)?;
+        let marker = match self.current_and_previous() {
+            (Some(current), _) if current == pgid => '+',
+            (_, Some(previous)) if previous == pgid => '-',
+            _ => ' ',
+        };
+        Some(Notice {
+            pgid,
+            jid: job.jid,
+            marker,
+            state: job.state,
+            cmd: job.cmd.clone(),
+        })
+    }
+
+    /// Queues a notice until the next prompt
+    pub fn push_notice(&mut self, notice: Notice) {
+        self.notices.push(notice);
+    }
+
+    /// Takes the notices queued since the last prompt and removes every
+    /// finished job, which counts as reported from then on
+    pub fn take_notices(&mut self) -> Vec<Notice> {
+        self.jobs.retain(|job| !job.state.finished());
+        self.recent
+            .retain(|&pgid| self.jobs.iter().any(|job| job.pid == pgid));
+        std::mem::take(&mut self.notices)
+    }
+
+    /// Removes the job pid belongs to if it finished, once `jobs` or `wait`
+    /// reported it
+    pub fn forget(&mut self, pid: Pid) {
+        if self.get_pid(pid).is_ok_and(|job| job.state.finished()) {
+            let _ = self.remove_job(pid);
+        }
+    }
+
+    /// Marks the job with process group pgid as the most recently used one
+    pub fn touch(&mut self, pgid: Pid) {
+        self.recent.retain(|&recent| recent != pgid);
+        self.recent.push(pgid);
+    }
+
+    /// The current job `%+` and the previous job `%-`: the two most recently
+    /// used background jobs, where stopped jobs come before running ones
+    pub fn current_and_previous(&self) -> (Option<Pid>, Option<Pid>) {
+        let mut ranked: Vec<(bool, usize, Pid)> = self
+            .jobs
+            .iter()
+            .filter(|job| !matches!(job.state, States::Running { fg: true }))
+            .filter_map(|job| {
+                let rank = self.recent.iter().position(|&recent| recent == job.pid)?;
+                Some((matches!(job.state, States::Stopped(_)), rank, job.pid))
+            })
+            .collect();
+        ranked.sort();
+        let mut ranked = ranked.into_iter().rev().map(|(_, _, pgid)| pgid);
+        (ranked.next(), ranked.next())
+    }
+
+    /// Resolves a job spec: `%n`, `%+` or `%%` for the current job, `%-` for
+    /// the previous one, `%name` for the job whose command starts with name
+    /// and `%?string` for the one whose command contains string
+    pub fn resolve(&mut self, spec: &str) -> Result<&mut Job, JobError> {
+        let name = spec.strip_prefix('%').unwrap_or(spec);
+        if let Ok(jid) = name.parse::<u32>() {
+            return self.get_jid_mut(jid);
+        }
+        let (current, previous) = self.current_and_previous();
+        let pgid = match name {
+            "" | "%" | "+" => current,
+            // with a single job `%-` means that one
+            "-" => previous.or(current),
+            pattern => {
+                let mut matches = self
+                    .jobs
+                    .iter()
+                    .filter(|job| match pattern.strip_prefix('?') {
+                        Some(substring) => job.cmd.contains(substring),
+                        None => job.cmd.starts_with(pattern),
+                    });
+                let found = matches.next().map(|job| job.pid);
+                if matches.next().is_some() {
+                    return Err(JobError::Ambiguous(spec.to_string()));
+                }
+                found
+            }
+        };
+        pgid.and_then(|pgid| self.jobs.iter_mut().find(|job| job.pid == pgid))
+            .ok_or(JobError::NoSuchJob(spec.to_string()))
+    }
+
+    pub fn set_job_control(&mut self, control: bool) {
+        self.control = control;
+    }
+
+    pub fn job_control(&self) -> bool {
+        self.control
+    }
 }
 
 impl Job {
-    pub fn new(pid: Pid, state: States, cmd: String) -> Self {
+    pub fn new(procs: Vec<Process>, state: States, cmd: String) -> Self {
         Self {
-            pid,
+            pid: procs[0].pid,
             state,
             cmd,
+            procs,
             jid: u32::MAX,
+            tmodes: None,
+            nohup: false,
+            usage: Usage::default(),
+            history: vec![(state, Instant::now())],
+        }
+    }
+
+    /// Moves the job to state next, unless it can't get there from where it
+    /// is
+    pub fn transition(&mut self, next: States) -> Result<(), JobError> {
+        if !self.state.allows(&next) {
+            return Err(match self.state.finished() {
+                true => JobError::Terminated,
+                false => JobError::Transition(self.state, next),
+            });
+        }
+        self.state = next;
+        self.history.push((next, Instant::now()));
+        Ok(())
+    }
+
+    pub fn started(&self) -> Instant {
+        self.history[0].1
+    }
+
+    /// When the job finished
+    pub fn ended(&self) -> Option<Instant> {
+        match self.history.last() {
+            Some((state, time)) if state.finished() => Some(*time),
+            _ => None,
+        }
+    }
+
+    pub fn has_pid(&self, pid: Pid) -> bool {
+        self.procs.iter().any(|proc| proc.pid == pid)
+    }
+
+    /// Marks one process of the job as finished, returns true once every
+    /// process in the pipeline has finished
+    pub fn reap(&mut self, pid: Pid, status: Status, rusage: &libc::rusage) -> bool {
+        if let Some(proc) = self.procs.iter_mut().find(|proc| proc.pid == pid) {
+            proc.status = Some(status);
+            self.usage.add(rusage);
+        }
+        self.procs.iter().all(|proc| proc.status.is_some())
+    }
+
+    /// Every process of the pipeline with its status, for `jobs -l`
+    pub fn processes(&self) -> String {
+        let lines: Vec<String> = self
+            .procs
+            .iter()
+            .map(|proc| match proc.status {
+                Some(status) => format!("    {} {}", proc.pid, States::from(status)),
+                None => format!("    {} {}", proc.pid, self.state),
+            })
+            .collect();
+        lines.join("\n")
+    }
+
+    /// The job as a JSON object, for `jobs --json`
+    pub fn json(&self) -> String {
+        let procs: Vec<String> = self
+            .procs
+            .iter()
+            .map(|proc| {
+                let (exit, signal) = match proc.status {
+                    Some(Status::Exited(code)) => (code.to_string(), "null".to_string()),
+                    Some(Status::Signaled(signal, _)) => ("null".to_string(), signal.to_string()),
+                    None => ("null".to_string(), "null".to_string()),
+                };
+                format!(
+                    "{{\"pid\":{},\"exit\":{},\"signal\":{}}}",
+                    proc.pid, exit, signal
+                )
+            })
+            .collect();
+        format!(
+            "{{\"jid\":{},\"pgid\":{},\"state\":{},\"command\":{},\"processes\":[{}]}}",
+            self.jid,
+            self.pid,
+            json_string(&self.state.to_string()),
+            json_string(self.cmd.trim()),
+            procs.join(",")
+        )
+    }
+
+    /// Resource usage for `jobs -v`, CPU time only counts processes that
+    /// finished already
+    pub fn usage_line(&self) -> String {
+        let real = match self.ended() {
+            Some(ended) => ended - self.started(),
+            None => self.started().elapsed(),
+        };
+        format!(
+            "    real {}  user {}  sys {}  maxrss {}k",
+            minutes(real),
+            minutes(self.usage.user),
+            minutes(self.usage.system),
+            self.usage.maxrss
+        )
+    }
+
+    /// Exit status of the job, which is the status of its last process
+    pub fn status(&self) -> Option<i32> {
+        States::from(self.procs.last()?.status?).code()
+    }
+
+    /// How the job ended once all its processes did: killed by a signal if
+    /// one hit it, otherwise exited like its last process. A pipeline reader
+    /// dying to SIGPIPE is the normal way for an upstream stage to go, so
+    /// that only counts when it hit the last process.
+    pub fn outcome(&self) -> Option<States> {
+        let mut statuses = Vec::with_capacity(self.procs.len());
+        for proc in &self.procs {
+            statuses.push(proc.status?);
         }
+        let last = statuses.len() - 1;
+        let signaled = statuses
+            .iter()
+            .enumerate()
+            .rev()
+            .filter_map(|(i, status)| match *status {
+                Status::Signaled(signal, core) => Some((i, signal, core)),
+                _ => None,
+            })
+            .find(|&(i, signal, _)| i == last || signal != nix::libc::SIGPIPE);
+        Some(match signaled {
+            Some((_, signal, core)) => States::Signaled(signal, core),
+            None => statuses[last].into(),
+        })
+    }
+}
+
+impl Process {
+    pub fn new(pid: Pid) -> Self {
+        Self { pid, status: None }
     }
 }
@@ -0,0 +1,458 @@
+use std::{fmt::Display, iter::Peekable, ops::Range, os::fd::RawFd, str::CharIndices};
+
+use crate::{
+    ast::{Param, Word, WordPart},
+    vars,
+};
+
+/// Byte range of a token in the source line
+pub type Span = Range<usize>;
+
+#[derive(Debug)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum TokenKind {
+    Word(Word),
+    /// `NAME=word`
+    Assignment(String, Word),
+    /// `|`
+    Pipe,
+    /// `&&`
+    And,
+    /// `||`
+    Or,
+    /// `;`
+    Semi,
+    /// `&`
+    Amp,
+    Newline,
+    /// `[n]<`
+    Read(RawFd),
+    /// `[n]>`
+    Write(RawFd),
+    /// `[n]>>`
+    Append(RawFd),
+    /// `&>`
+    WriteAll,
+    /// `[n]>&m`
+    Dup(RawFd, RawFd),
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub message: String,
+    /// 1-based column, counted in characters
+    pub column: usize,
+    /// The line ended inside a quote or after an operator, so the lines
+    /// after it can complete it
+    pub incomplete: bool,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at column {}", self.message, self.column)
+    }
+}
+
+impl ParseError {
+    pub fn new(src: &str, pos: usize, message: String) -> Self {
+        Self {
+            message,
+            column: src[..pos].chars().count() + 1,
+            incomplete: false,
+        }
+    }
+}
+
+impl Display for TokenKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Word(_) | Self::Assignment(_, _) => write!(f, "word"),
+            Self::Pipe => write!(f, "|"),
+            Self::And => write!(f, "&&"),
+            Self::Or => write!(f, "||"),
+            Self::Semi => write!(f, ";"),
+            Self::Amp => write!(f, "&"),
+            Self::Newline => write!(f, "newline"),
+            Self::Read(fd) => write!(f, "{}<", fd),
+            Self::Write(fd) => write!(f, "{}>", fd),
+            Self::Append(fd) => write!(f, "{}>>", fd),
+            Self::WriteAll => write!(f, "&>"),
+            Self::Dup(fd, target) => write!(f, "{}>&{}", fd, target),
+        }
+    }
+}
+
+/// Characters a backslash can escape outside of quotes. Before anything else
+/// the backslash is kept, so `/bin/echo -e ... \046` in the trace files still
+/// reaches echo intact.
+pub const ESCAPABLE: &str = " \t\n'\"\\$|&;<>()#`*?[]~{}";
+
+/// Splits a line into tokens.
+///
+/// `<` and `>` only start a redirection at the beginning of a word, so
+/// `/bin/echo tsh> cmd` prints the prompt like the reference shell does.
+/// `|`, `&` and `;` end a word wherever they appear.
+pub struct Lexer<'a> {
+    src: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(src: &'a str) -> Self {
+        Self {
+            src,
+            chars: src.char_indices().peekable(),
+        }
+    }
+
+    pub fn tokens(mut self) -> Result<Vec<Token>, ParseError> {
+        let mut tokens = vec![];
+        while let Some(token) = self.next_token()? {
+            tokens.push(token);
+        }
+        Ok(tokens)
+    }
+
+    fn pos(&mut self) -> usize {
+        match self.chars.peek() {
+            Some(&(pos, _)) => pos,
+            None => self.src.len(),
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.chars.next_if(|&(_, next)| next == c).is_some()
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, ParseError> {
+        while self
+            .chars
+            .next_if(|&(_, c)| c == ' ' || c == '\t')
+            .is_some()
+        {}
+        let start = self.pos();
+        let c = match self.chars.peek() {
+            Some(&(_, c)) => c,
+            None => return Ok(None),
+        };
+
+        let kind = match c {
+            '#' => {
+                while self.chars.next_if(|&(_, c)| c != '\n').is_some() {}
+                return self.next_token();
+            }
+            '\n' => {
+                self.chars.next();
+                TokenKind::Newline
+            }
+            ';' => {
+                self.chars.next();
+                TokenKind::Semi
+            }
+            '|' => {
+                self.chars.next();
+                if self.eat('|') {
+                    TokenKind::Or
+                } else {
+                    TokenKind::Pipe
+                }
+            }
+            '&' => {
+                self.chars.next();
+                if self.eat('&') {
+                    TokenKind::And
+                } else if self.eat('>') {
+                    TokenKind::WriteAll
+                } else {
+                    TokenKind::Amp
+                }
+            }
+            _ => match self.redirect()? {
+                Some(kind) => kind,
+                None => match self.assignment()? {
+                    Some(kind) => kind,
+                    None => TokenKind::Word(self.word()?),
+                },
+            },
+        };
+
+        Ok(Some(Token {
+            kind,
+            span: start..self.pos(),
+        }))
+    }
+
+    /// Lexes `[n]<`, `[n]>`, `[n]>>` and `[n]>&m` at the start of a word
+    fn redirect(&mut self) -> Result<Option<TokenKind>, ParseError> {
+        let start = self.pos();
+        let rest = &self.src[start..];
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let fd = match rest[..digits].parse::<RawFd>() {
+            Ok(fd) => Some(fd),
+            Err(_) if digits == 0 => None,
+            Err(_) => return Ok(None),
+        };
+        let kind = match rest[digits..].chars().next() {
+            Some('<') => TokenKind::Read(fd.unwrap_or(0)),
+            Some('>') => {
+                let after = &rest[digits + 1..];
+                if after.starts_with('>') {
+                    TokenKind::Append(fd.unwrap_or(1))
+                } else if let Some(target) = after.strip_prefix('&') {
+                    let len = target.len()
+                        - target
+                            .trim_start_matches(|c: char| c.is_ascii_digit())
+                            .len();
+                    match target[..len].parse::<RawFd>() {
+                        Ok(target) => TokenKind::Dup(fd.unwrap_or(1), target),
+                        Err(_) => {
+                            return Err(ParseError::new(
+                                self.src,
+                                start + digits + 2,
+                                "expected a file descriptor after `>&'".to_string(),
+                            ))
+                        }
+                    }
+                } else {
+                    TokenKind::Write(fd.unwrap_or(1))
+                }
+            }
+            _ => return Ok(None),
+        };
+        let len = match &kind {
+            TokenKind::Read(_) | TokenKind::Write(_) => digits + 1,
+            TokenKind::Append(_) => digits + 2,
+            _ => {
+                let target = &rest[digits + 2..];
+                digits + 2 + target.len()
+                    - target
+                        .trim_start_matches(|c: char| c.is_ascii_digit())
+                        .len()
+            }
+        };
+        while self.pos() < start + len {
+            self.chars.next();
+        }
+        Ok(Some(kind))
+    }
+
+    /// Lexes `NAME=word` at the start of a word
+    fn assignment(&mut self) -> Result<Option<TokenKind>, ParseError> {
+        let rest = &self.src[self.pos()..];
+        let name = match rest.split_once('=') {
+            Some((name, _)) if vars::valid_name(name) => name,
+            _ => return Ok(None),
+        };
+        for _ in 0..=name.len() {
+            self.chars.next();
+        }
+        Ok(Some(TokenKind::Assignment(name.to_string(), self.word()?)))
+    }
+
+    fn word(&mut self) -> Result<Word, ParseError> {
+        Ok(Word {
+            parts: self.parts(false)?,
+        })
+    }
+
+    /// Lexes the pieces of a word. Inside `${NAME:-...}` only the closing
+    /// brace ends it.
+    fn parts(&mut self, in_braces: bool) -> Result<Vec<WordPart>, ParseError> {
+        let mut parts: Vec<WordPart> = vec![];
+        let mut literal = String::new();
+        if let Some(user) = self.tilde(in_braces) {
+            parts.push(WordPart::Tilde(user));
+        }
+        while let Some(&(pos, c)) = self.chars.peek() {
+            match c {
+                '}' if in_braces => break,
+                ' ' | '\t' | '\n' | '|' | '&' | ';' if !in_braces => break,
+                '\'' => {
+                    self.chars.next();
+                    let before = parts.len();
+                    loop {
+                        match self.chars.next() {
+                            Some((_, '\'')) => break,
+                            Some((_, c)) => literal.push(c),
+                            None => return Err(unterminated(self.src, pos)),
+                        }
+                    }
+                    empty_quotes(&mut parts, &literal, before);
+                }
+                '"' => {
+                    self.chars.next();
+                    let before = parts.len();
+                    loop {
+                        match self.chars.next() {
+                            Some((_, '"')) => break,
+                            Some((_, '\\')) => match self.chars.peek() {
+                                Some(&(_, c @ ('$' | '`' | '"' | '\\'))) => {
+                                    self.chars.next();
+                                    literal.push(c);
+                                }
+                                Some(&(_, '\n')) => {
+                                    self.chars.next();
+                                }
+                                _ => literal.push('\\'),
+                            },
+                            Some((pos, '$')) => self.dollar(pos, &mut parts, &mut literal, true)?,
+                            Some((_, c)) => literal.push(c),
+                            None => return Err(unterminated(self.src, pos)),
+                        }
+                    }
+                    empty_quotes(&mut parts, &literal, before);
+                }
+                '\\' => {
+                    self.chars.next();
+                    match self.chars.peek() {
+                        Some(&(_, '\n')) => {
+                            self.chars.next();
+                        }
+                        Some(&(_, c)) if ESCAPABLE.contains(c) => {
+                            self.chars.next();
+                            literal.push(c);
+                        }
+                        _ => literal.push('\\'),
+                    }
+                }
+                '$' => {
+                    self.chars.next();
+                    self.dollar(pos, &mut parts, &mut literal, false)?;
+                }
+                _ => {
+                    self.chars.next();
+                    literal.push(c);
+                }
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(WordPart::Literal(literal));
+        }
+        Ok(parts)
+    }
+
+    /// Lexes an unquoted `~` or `~user` at the start of a word, as long as
+    /// it is followed by a slash or ends the word
+    fn tilde(&mut self, in_braces: bool) -> Option<String> {
+        let rest = self.src[self.pos()..].strip_prefix('~')?;
+        let len = rest.len()
+            - rest
+                .trim_start_matches(|c: char| c.is_ascii_alphanumeric() || "._-".contains(c))
+                .len();
+        match rest[len..].chars().next() {
+            None | Some('/') => {}
+            Some('}') if in_braces => {}
+            Some(' ' | '\t' | '\n' | '|' | '&' | ';') if !in_braces => {}
+            _ => return None,
+        }
+        for _ in 0..=len {
+            self.chars.next();
+        }
+        Some(rest[..len].to_string())
+    }
+
+    /// Lexes `$?`, `$#`, `$@`, `$!`, `$0`..`$9`, `$NAME`, `${NAME}` and
+    /// `${NAME:-word}`, start is the position of the `$`, which has already
+    /// been consumed, quoted whether it is inside double quotes
+    fn dollar(
+        &mut self,
+        start: usize,
+        parts: &mut Vec<WordPart>,
+        literal: &mut String,
+        quoted: bool,
+    ) -> Result<(), ParseError> {
+        let braced = self.eat('{');
+        let name = if let Some((_, c)) = self.chars.next_if(|&(_, c)| "?#@!".contains(c)) {
+            c.to_string()
+        } else if let Some((_, c)) = self.chars.next_if(|&(_, c)| c.is_ascii_digit()) {
+            // only `${10}` reaches past the first digit
+            let mut name = c.to_string();
+            while let Some((_, c)) = self.chars.next_if(|&(_, c)| braced && c.is_ascii_digit()) {
+                name.push(c);
+            }
+            name
+        } else {
+            let mut name = String::new();
+            if let Some((_, c)) = self
+                .chars
+                .next_if(|&(_, c)| c.is_ascii_alphabetic() || c == '_')
+            {
+                name.push(c);
+                while let Some((_, c)) = self
+                    .chars
+                    .next_if(|&(_, c)| c.is_ascii_alphanumeric() || c == '_')
+                {
+                    name.push(c);
+                }
+            }
+            name
+        };
+
+        if !braced {
+            if name.is_empty() {
+                literal.push('$');
+                return Ok(());
+            }
+            return self.param(parts, literal, Param::new(name, None, quoted));
+        }
+
+        let default = if self.eat(':') {
+            if !self.eat('-') {
+                return Err(ParseError::new(
+                    self.src,
+                    start,
+                    "bad substitution".to_string(),
+                ));
+            }
+            Some(Word {
+                parts: self.parts(true)?,
+            })
+        } else {
+            None
+        };
+        if name.is_empty() || !self.eat('}') {
+            return Err(ParseError::new(
+                self.src,
+                start,
+                match self.chars.peek() {
+                    None | Some((_, '\n')) => "unterminated `${'".to_string(),
+                    Some(_) => "bad substitution".to_string(),
+                },
+            ));
+        }
+        self.param(parts, literal, Param::new(name, default, quoted))
+    }
+
+    fn param(
+        &mut self,
+        parts: &mut Vec<WordPart>,
+        literal: &mut String,
+        param: Param,
+    ) -> Result<(), ParseError> {
+        if !literal.is_empty() {
+            parts.push(WordPart::Literal(std::mem::take(literal)));
+        }
+        parts.push(WordPart::Param(param));
+        Ok(())
+    }
+}
+
+/// Quotes that held nothing, like `''`, still make a field, so they leave
+/// an empty literal when no part was added since before
+fn empty_quotes(parts: &mut Vec<WordPart>, literal: &str, before: usize) {
+    if literal.is_empty() && parts.len() == before {
+        parts.push(WordPart::Literal(String::new()));
+    }
+}
+
+fn unterminated(src: &str, pos: usize) -> ParseError {
+    ParseError {
+        incomplete: true,
+        ..ParseError::new(src, pos, "unterminated quote".to_string())
+    }
+}
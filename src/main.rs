@@ -1,44 +1,82 @@
+mod ast;
+mod complete;
+mod dirs;
+mod editor;
+mod expand;
+mod hash;
 mod helpers;
+mod history;
 mod jobs;
+mod lexer;
+mod parser;
+mod prompt;
+mod redirect;
+mod terminal;
+mod vars;
 
-use crate::jobs::Job;
-use helpers::unix_error;
-use jobs::{JobManager, Jobs, States};
+use crate::jobs::{minutes, Job, Process, Status, Usage};
+use ast::{Connector, Pipeline};
+use complete::{Completer, Completions, Spec};
+use dirs::DirStack;
+use editor::Editor;
+use expand::Expanded;
+use hash::PathCache;
+use helpers::{unix_error, Options};
+use history::History;
+use jobs::{JobError, JobManager, Jobs, States};
 use nix::{
     errno::Errno,
+    fcntl::{fcntl, FcntlArg, OFlag},
+    libc::{self, STDIN_FILENO, STDOUT_FILENO},
     sys::{
-        signal::{kill, sigprocmask, SigmaskHow, Signal},
-        signalfd::SigSet,
-        wait::{waitpid, WaitPidFlag, WaitStatus},
+        signal::{killpg, signal, SigHandler, Signal},
+        wait::{WaitPidFlag, WaitStatus},
     },
-    unistd::{execv, fork, setpgid, ForkResult, Pid},
+    unistd::{dup2, execve, fork, getpid, isatty, pipe2, setpgid, tcsetpgrp, ForkResult, Pid},
 };
+use redirect::Redirections;
 use std::{
     env::args,
     ffi::{CStr, CString},
-    io::{stdin, stdout, Write},
+    fs::File,
+    io::{stdin, stdout, BufRead, BufReader, Cursor, ErrorKind, Read, Write},
+    os::{
+        fd::{AsRawFd, IntoRawFd, OwnedFd},
+        unix::ffi::OsStringExt,
+    },
+    path::PathBuf,
     process::exit,
     sync::{
+        atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering},
         mpsc,
         mpsc::{Receiver, Sender},
-        LazyLock, Mutex,
+        Condvar, LazyLock, Mutex,
     },
+    time::Instant,
 };
+use terminal::Terminal;
+use vars::Variables;
 
 use i32 as sig_t;
-#[derive(Debug)]
-enum MessageQueue {
-    RemoveJob { pid: Pid },
-    Stopped { pid: Pid, signal: i32 },
-    Signaled { pid: Pid, signal: i32 },
-    Signal { signal: i32 },
-}
 
 const PROMT_STR: &str = "tsh> ";
+const BUILTINS: [&str; 20] = [
+    "quit", "jobs", "bg", "fg", "hash", "type", "which", "export", "unset", "set", "cd", "pwd",
+    "pushd", "popd", "dirs", "kill", "wait", "disown", "history", "complete",
+];
 
-static VERBOSE: LazyLock<bool> =
-    LazyLock::new(|| args().any(|arg| arg == "-v" || arg == "--verbose"));
-static PROMT: LazyLock<bool> = LazyLock::new(|| args().all(|arg| arg != "-p" && arg != "--prompt"));
+static OPTIONS: LazyLock<Options> = LazyLock::new(|| match helpers::parse_args(args()) {
+    Ok(options) => options,
+    Err(e) => {
+        println!("tsh: {}", e);
+        helpers::usage();
+        exit(2)
+    }
+});
+static VERBOSE: LazyLock<bool> = LazyLock::new(|| OPTIONS.verbose);
+static PROMT: LazyLock<bool> = LazyLock::new(|| !OPTIONS.no_prompt && OPTIONS.interactive());
+/// Lines are typed at a terminal, so they are edited and kept in the history
+static EDITING: LazyLock<bool> = LazyLock::new(|| *PROMT && isatty(STDIN_FILENO).unwrap_or(false));
 
 type Key = Mutex<Sender<()>>;
 type Lock = Mutex<Receiver<()>>;
@@ -47,13 +85,43 @@ static LOCK: LazyLock<(Key, Lock)> = LazyLock::new(|| {
     (Mutex::new(tx), Mutex::new(rx))
 });
 static JOBMANAGER: LazyLock<Mutex<JobManager>> = LazyLock::new(|| Mutex::new(JobManager::new()));
+/// Exit status of the last foreground command, `$?`
+static STATUS: AtomicI32 = AtomicI32::new(0);
+/// What the last foreground job to finish used, for `time`
+static USAGE: LazyLock<Mutex<Usage>> = LazyLock::new(|| Mutex::new(Usage::default()));
+static HASH: LazyLock<Mutex<PathCache>> = LazyLock::new(|| Mutex::new(PathCache::new()));
+static VARS: LazyLock<Mutex<Variables>> = LazyLock::new(|| Mutex::new(Variables::new()));
+static DIRS: LazyLock<Mutex<DirStack>> = LazyLock::new(|| Mutex::new(DirStack::new()));
+/// Lines read by an interactive shell
+static HISTORY: LazyLock<Mutex<History>> = LazyLock::new(|| Mutex::new(History::new()));
 
-type SenderT = Mutex<Sender<MessageQueue>>;
-type ReceiverT = Mutex<Receiver<MessageQueue>>;
-static MESSAGES: LazyLock<(SenderT, ReceiverT)> = LazyLock::new(|| {
-    let (tx, rx) = mpsc::channel::<MessageQueue>();
-    (Mutex::new(tx), Mutex::new(rx))
-});
+/// Write end of the self-pipe the signal handler reports signals through
+static SIGNALS: AtomicI32 = AtomicI32::new(-1);
+/// Held while a pipeline is forked and while the event loop reaps, so a
+/// child can't be reaped before its job exists. Children never take it.
+static FORKING: Mutex<()> = Mutex::new(());
+/// Notified by the event loop whenever a job finishes or stops, waited on
+/// together with `JOBMANAGER`
+static CHANGED: Condvar = Condvar::new();
+/// Set by SIGINT while no job is in the foreground, which cuts `wait` short
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// `set -b`: report finished background jobs right away instead of before
+/// the next prompt
+static NOTIFY: AtomicBool = AtomicBool::new(false);
+/// Number of command lines read so far
+static LINES: AtomicUsize = AtomicUsize::new(0);
+/// One past the line on which the user last saw the stopped jobs, through
+/// the warning or `jobs`. Leaving right after that goes through.
+static WARNED: AtomicUsize = AtomicUsize::new(0);
+/// The shell's own pid, builtins running in a pipeline are not the shell
+static SHELL_PID: LazyLock<Pid> = LazyLock::new(getpid);
+/// Set when the shell is interactive on a terminal
+static TERMINAL: Mutex<Option<Terminal>> = Mutex::new(None);
+/// Reads command lines typed at the prompt. Whatever is printed while one is
+/// being edited goes through it.
+static EDITOR: Editor = Editor::new(complete);
+/// What the arguments of each command complete to
+static COMPLETER: LazyLock<Mutex<Completer>> = LazyLock::new(|| Mutex::new(Completer::new()));
 
 macro_rules! log {
     ($($arg:tt)*) => {
@@ -68,368 +136,1589 @@ fn main() {
         println!("tsh: Version 1.0");
     }
 
-    if args().any(|arg| arg == "-h" || arg == "--help") {
+    if OPTIONS.help {
         helpers::usage();
         return;
     }
 
-    unsafe {
-        init();
+    LazyLock::force(&SHELL_PID);
+    let events = unsafe { init() };
+    dirs::init(&mut VARS.lock().unwrap());
+    VARS.lock().unwrap().set_args(OPTIONS.args.clone());
+    JOBMANAGER
+        .lock()
+        .unwrap()
+        .set_job_control(OPTIONS.interactive());
+    if OPTIONS.interactive() {
+        *TERMINAL.lock().unwrap() = Terminal::new();
+    }
+    if *EDITING {
+        HISTORY.lock().unwrap().load(&VARS.lock().unwrap());
     }
 
-    start();
+    start(events);
 }
 
-unsafe fn init() {
-    use helpers::{set_handler, sigquit_handler};
+/// Installs the signal handler and returns the read end of its self-pipe
+unsafe fn init() -> OwnedFd {
+    use helpers::set_handler;
 
-    match set_handler(Signal::SIGQUIT, sigquit_handler) {
+    let (events, signals) = match pipe2(OFlag::O_CLOEXEC) {
+        Ok(pipe) => pipe,
+        Err(_e) => unix_error("Cannot create pipe"),
+    };
+    // a full pipe already holds a wakeup, the handler must never block on it
+    match fcntl(signals.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK)) {
         Ok(_) => {}
-        Err(_e) => unix_error("Set SIGQUIT handler failed"),
+        Err(_e) => unix_error("Cannot set up the signal pipe"),
     }
-    match set_handler(Signal::SIGTSTP, sigstp_handler) {
+    SIGNALS.store(signals.into_raw_fd(), Ordering::SeqCst);
+
+    match set_handler(Signal::SIGQUIT, signal_handler) {
         Ok(_) => {}
-        Err(_e) => unix_error("Set SIGSTOP handler failed"),
+        Err(_e) => unix_error("Set SIGQUIT handler failed"),
     }
-    match set_handler(Signal::SIGINT, sigint_handler) {
-        Ok(_) => {}
-        Err(_e) => unix_error("Set SIGINT handler failed"),
+    // without job control children share the shell's process group, so the
+    // terminal's signals reach them without being forwarded
+    if OPTIONS.interactive() {
+        match set_handler(Signal::SIGTSTP, signal_handler) {
+            Ok(_) => {}
+            Err(_e) => unix_error("Set SIGSTOP handler failed"),
+        }
+        match set_handler(Signal::SIGINT, signal_handler) {
+            Ok(_) => {}
+            Err(_e) => unix_error("Set SIGINT handler failed"),
+        }
+        match set_handler(Signal::SIGHUP, signal_handler) {
+            Ok(_) => {}
+            Err(_e) => unix_error("Set SIGHUP handler failed"),
+        }
     }
-    match set_handler(Signal::SIGCHLD, sigchld_handler) {
+    match set_handler(Signal::SIGCHLD, signal_handler) {
         Ok(_) => {}
         Err(_e) => unix_error("Set SIGCHLD handler failed"),
     }
+    events
 }
 
-fn start() {
-    std::thread::spawn(receiver);
+fn start(events: OwnedFd) {
+    std::thread::spawn(move || event_loop(events));
+    let mut input: Box<dyn BufRead> = if let Some(command) = &OPTIONS.command {
+        Box::new(Cursor::new(command.clone()))
+    } else if let Some(script) = &OPTIONS.script {
+        match File::open(script) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(e) => {
+                let errno = Errno::from_raw(e.raw_os_error().unwrap_or(0));
+                println!("tsh: {}: {}", script, errno.desc());
+                exit(127);
+            }
+        }
+    } else {
+        Box::new(stdin().lock())
+    };
     loop {
-        {}
         let mut line = String::new();
+        let notices = JOBMANAGER.lock().unwrap().take_notices();
         if *PROMT {
-            print!("{}", PROMT_STR);
-            match stdout().flush() {
-                Ok(_) => {}
-                Err(e) => unix_error(&dbg!(e).to_string()),
-            };
-        }
-
-        match stdin().read_line(&mut line) {
-            Ok(_) => {}
-            Err(e) => {
-                unix_error(&dbg!(e).to_string());
+            for notice in notices {
+                println!("{}", notice);
             }
         }
+        let ps1 = prompt("PS1", PROMT_STR);
+        read_line(&mut *input, &ps1, &mut line);
         if line.is_empty() {
-            break;
+            leave(STATUS.load(Ordering::SeqCst));
+            continue;
+        }
+        // a line ending in an open quote or after `|`, `&&` or `||` goes on
+        // on the next one, in scripts too
+        while parser::parse(&line).is_err_and(|e| e.incomplete) {
+            if read_line(&mut *input, &prompt("PS2", "> "), &mut line) == 0 {
+                break;
+            }
+        }
+        LINES.fetch_add(1, Ordering::SeqCst);
+        if *EDITING {
+            let mut history = HISTORY.lock().unwrap();
+            match history.expand(&line) {
+                Ok(Some(expanded)) => {
+                    println!("{}", expanded.trim_end());
+                    line = expanded;
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    println!("tsh: {}", e);
+                    continue;
+                }
+            }
+            history.add(&VARS.lock().unwrap(), &line);
         }
         eval(&line);
     }
 }
 
+/// Appends a line from input to line, showing prompt first when there is
+/// one. Returns the length of what was read, 0 at the end of input.
+fn read_line(input: &mut dyn BufRead, prompt: &str, line: &mut String) -> usize {
+    let read = match *EDITING {
+        true => {
+            let history = HISTORY.lock().unwrap().entries().to_vec();
+            EDITOR.read_line(prompt, history, line)
+        }
+        false => {
+            if *PROMT {
+                print!("{}", prompt::strip(prompt));
+                match stdout().flush() {
+                    Ok(_) => {}
+                    Err(e) => unix_error(&dbg!(e).to_string()),
+                };
+            }
+            input.read_line(line)
+        }
+    };
+    match read {
+        Ok(len) => len,
+        Err(e) => unix_error(&dbg!(e).to_string()),
+    }
+}
+
+/// The value of the prompt variable name with its escapes expanded, or
+/// default when it is unset
+fn prompt(name: &str, default: &str) -> String {
+    let vars = VARS.lock().unwrap();
+    let Some(template) = vars.get(name) else {
+        return default.to_string();
+    };
+    let manager = JOBMANAGER.lock().unwrap();
+    let count = |state: fn(&States) -> bool| manager.jobs().filter(|job| state(&job.state)).count();
+    let context = prompt::Context {
+        vars: &vars,
+        status: STATUS.load(Ordering::SeqCst),
+        jobs: count(|state| !state.finished()),
+        running: count(|state| matches!(state, States::Running { .. })),
+        stopped: count(|state| matches!(state, States::Stopped(_))),
+    };
+    prompt::render(template, &context)
+}
+
+/// Exits the shell with status, sending SIGHUP to its jobs. While jobs are
+/// stopped the first attempt from a terminal only warns and returns.
+fn leave(status: i32) {
+    let _ = stdout().flush();
+    if getpid() != *SHELL_PID {
+        exit(status);
+    }
+    let manager = JOBMANAGER.lock().unwrap();
+    if manager.job_control() {
+        let line = LINES.load(Ordering::SeqCst);
+        let terminal = TERMINAL.lock().unwrap().is_some();
+        if terminal && manager.stopped() && WARNED.load(Ordering::SeqCst) < line.max(1) {
+            WARNED.store(line + 1, Ordering::SeqCst);
+            println!("There are stopped jobs.");
+            return;
+        }
+        hangup(&manager);
+    }
+    if *EDITING {
+        HISTORY.lock().unwrap().save(&VARS.lock().unwrap());
+    }
+    exit(status);
+}
+
+/// Sends SIGHUP and then SIGCONT to every job not protected by `disown -h`.
+/// Only an interactive shell does this, a script leaves its jobs running.
+fn hangup(manager: &JobManager) {
+    for job in manager.jobs().filter(|job| !job.nohup) {
+        let _ = job.kill(Some(Signal::SIGHUP), true);
+        let _ = job.kill(Some(Signal::SIGCONT), true);
+    }
+}
+
 fn eval(line: &str) {
-    let (argv, isbg) = helpers::parse_line(line);
+    let list = match parser::parse(line) {
+        Ok(list) => list,
+        Err(e) => {
+            println!("tsh: {}", e);
+            STATUS.store(2, Ordering::SeqCst);
+            return;
+        }
+    };
+
+    let mut connector = Connector::Seq;
+    for item in &list.items {
+        let skip = match connector {
+            Connector::And => STATUS.load(Ordering::SeqCst) != 0,
+            Connector::Or => STATUS.load(Ordering::SeqCst) == 0,
+            _ => false,
+        };
+        connector = item.connector;
+        if skip {
+            continue;
+        }
+        let text = &line[item.pipeline.span.clone()];
+        if item.connector == Connector::Background {
+            run(&format!("{} &", text), &item.pipeline, true);
+        } else if item.pipeline.timed {
+            *USAGE.lock().unwrap() = Usage::default();
+            let started = Instant::now();
+            run(text, &item.pipeline, false);
+            let usage = *USAGE.lock().unwrap();
+            println!();
+            println!("real\t{}", minutes(started.elapsed()));
+            println!("user\t{}", minutes(usage.user));
+            println!("sys\t{}", minutes(usage.system));
+        } else {
+            run(text, &item.pipeline, false);
+        }
+    }
+}
 
-    if argv.is_empty() {
+/// Runs one pipeline of a list, cmd is the text shown for its job
+fn run(cmd: &str, pipeline: &Pipeline, isbg: bool) {
+    let stages: Vec<Expanded> = {
+        let vars = VARS.lock().unwrap();
+        pipeline
+            .commands
+            .iter()
+            .map(|command| expand::command(command, &vars, STATUS.load(Ordering::SeqCst)))
+            .collect()
+    };
+
+    if stages.len() == 1 && (stages[0].argv.is_empty() || BUILTINS.contains(&&*stages[0].argv[0])) {
+        let redirections = match Redirections::open(&stages[0].redirects) {
+            Ok(redirections) => redirections,
+            Err(e) => {
+                println!("tsh: {}", e);
+                STATUS.store(1, Ordering::SeqCst);
+                return;
+            }
+        };
+        if stages[0].argv.is_empty() {
+            for (name, value) in &stages[0].assignments {
+                assign(name, value.clone());
+            }
+            STATUS.store(0, Ordering::SeqCst);
+            return;
+        }
+        let _ = stdout().flush();
+        let saved = match redirections.apply_saved() {
+            Ok(saved) => saved,
+            Err(e) => {
+                println!("tsh: {}", e);
+                STATUS.store(1, Ordering::SeqCst);
+                return;
+            }
+        };
+        let status = builtin(&stages[0].argv).unwrap();
+        let _ = stdout().flush();
+        saved.restore();
+        STATUS.store(status, Ordering::SeqCst);
         return;
     }
 
+    exec(cmd, stages, isbg);
+}
+
+/// Sets a shell variable, forgetting hashed commands when `$PATH` changes
+fn assign(name: &str, value: String) {
+    if name == "PATH" {
+        HASH.lock().unwrap().clear();
+    }
+    VARS.lock().unwrap().set(name, value);
+}
+
+/// Runs argv if it names a builtin and returns its exit status
+fn builtin(argv: &[String]) -> Option<i32> {
     match argv[0].as_str() {
-        "quit" => exit(0),
-        "jobs" => println!("{}", JOBMANAGER.lock().unwrap().list().trim_end()),
+        "quit" => {
+            leave(0);
+            return Some(1);
+        }
+        "jobs" => return Some(jobs(&argv[1..])),
         "bg" => {
-            if argv.len() == 1 {
-                println!("bg command requires PID or %jobid argument");
-                return;
+            if !JOBMANAGER.lock().unwrap().job_control() {
+                println!("tsh: bg: no job control");
+                return Some(1);
             }
-            if let Ok(pid) = argv[1].parse::<i32>() {
-                if let Ok(job) = { JOBMANAGER.lock().unwrap().get_pid_mut(Pid::from_raw(pid)) } {
-                    job.bg();
+            let mut manager = JOBMANAGER.lock().unwrap();
+            let pgid = find_job(&mut manager, argv.get(1).map_or("%+", |arg| arg))
+                .and_then(|job| job.bg().map(|_| job.pid));
+            match pgid {
+                Ok(pgid) => manager.touch(pgid),
+                Err(JobError::NoSuchJob(_)) if argv.len() == 1 => {
+                    println!("bg command requires PID or %jobid argument");
+                    return Some(1);
                 }
-            } else if argv[1].chars().next().unwrap_or('\0') == '%' {
-                if let Ok(jid) = argv[1][1..].parse::<u32>() {
-                    if let Ok(job) = { JOBMANAGER.lock().unwrap().get_jid_mut(jid) } {
-                        job.bg();
-                    }
+                Err(e) => {
+                    job_error("bg", &e);
+                    return Some(1);
                 }
             }
         }
         "fg" => {
-            if argv.len() == 1 {
-                println!("fg command requires PID or %jobid argument");
-                return;
+            if !JOBMANAGER.lock().unwrap().job_control() {
+                println!("tsh: fg: no job control");
+                return Some(1);
             }
-            if let Some(pid) = if let Ok(pid) = argv[1].parse::<i32>() {
-                if let Ok(job) = JOBMANAGER.lock().unwrap().get_pid_mut(Pid::from_raw(pid)) {
-                    job.fg();
-                    Some(job.pid)
-                } else {
-                    None
-                }
-            } else if argv[1].chars().next().unwrap_or('\0') == '%' {
-                if let Ok(jid) = argv[1][1..].parse::<u32>() {
-                    if let Ok(job) = JOBMANAGER.lock().unwrap().get_jid_mut(jid) {
-                        job.fg();
-                        Some(job.pid)
-                    } else {
-                        None
+            // the job is continued and made the foreground job at once, so
+            // the event loop can't reap it as a background job in between
+            let forking = FORKING.lock().unwrap();
+            let mut manager = JOBMANAGER.lock().unwrap();
+            let pid = find_job(&mut manager, argv.get(1).map_or("%+", |arg| arg))
+                .and_then(|job| job.fg().map(|_| job.pid));
+            if let Ok(pid) = pid {
+                manager.set_fg(pid);
+                println!("{}", manager.list().trim_end());
+            }
+            drop(manager);
+            drop(forking);
+            match pid {
+                Ok(pid) => {
+                    waitfg(pid);
+                    return Some(STATUS.load(Ordering::SeqCst));
+                }
+                Err(JobError::NoSuchJob(_)) if argv.len() == 1 => {
+                    println!("fg command requires PID or %jobid argument");
+                    return Some(1);
+                }
+                Err(e) => {
+                    job_error("fg", &e);
+                    return Some(1);
+                }
+            }
+        }
+        "kill" => return Some(kill(&argv[1..])),
+        "disown" => return Some(disown(&argv[1..])),
+        "history" => return Some(history(&argv[1..])),
+        "complete" => return Some(complete_builtin(&argv[1..])),
+        "wait" => return Some(wait(&argv[1..])),
+        "hash" => {
+            let mut status = 0;
+            let mut hash = HASH.lock().unwrap();
+            match argv.get(1).map(|arg| arg.as_str()) {
+                None => print!("{}", hash.list()),
+                Some("-r") => hash.clear(),
+                Some(_) => {
+                    let paths = paths();
+                    for name in &argv[1..] {
+                        if !hash.add(name, &paths) {
+                            println!("tsh: hash: {}: not found", name);
+                            status = 1;
+                        }
                     }
+                }
+            }
+            return Some(status);
+        }
+        "type" => {
+            let mut status = 0;
+            for name in &argv[1..] {
+                if BUILTINS.contains(&name.as_str()) {
+                    println!("{} is a shell builtin", name);
+                } else if let Some(path) = HASH.lock().unwrap().get(name) {
+                    println!("{} is hashed ({})", name, path.display());
+                } else if let Some(path) = hash::search(name, &paths()) {
+                    println!("{} is {}", name, path.display());
                 } else {
-                    None
+                    println!("tsh: type: {}: not found", name);
+                    status = 1;
                 }
+            }
+            return Some(status);
+        }
+        "which" => {
+            let mut status = 0;
+            for name in &argv[1..] {
+                if BUILTINS.contains(&name.as_str()) {
+                    println!("{}: shell builtin", name);
+                } else if let Some(path) = HASH.lock().unwrap().get(name) {
+                    println!("{}", path.display());
+                } else if let Some(path) = hash::search(name, &paths()) {
+                    println!("{}", path.display());
+                } else {
+                    println!("{} not found", name);
+                    status = 1;
+                }
+            }
+            return Some(status);
+        }
+        "export" => {
+            if argv.len() == 1 {
+                print!("{}", VARS.lock().unwrap().list_exported());
+            }
+            let mut status = 0;
+            for arg in &argv[1..] {
+                let (name, value) = match arg.split_once('=') {
+                    Some((name, value)) => (name, Some(value)),
+                    None => (arg.as_str(), None),
+                };
+                if !vars::valid_name(name) {
+                    println!("tsh: export: `{}': not a valid identifier", arg);
+                    status = 1;
+                    continue;
+                }
+                if let Some(value) = value {
+                    assign(name, value.to_string());
+                }
+                VARS.lock().unwrap().export(name);
+            }
+            return Some(status);
+        }
+        "unset" => {
+            for name in &argv[1..] {
+                if name == "PATH" {
+                    HASH.lock().unwrap().clear();
+                }
+                VARS.lock().unwrap().unset(name);
+            }
+        }
+        "set" => return Some(set(&argv[1..])),
+        "cd" => {
+            let mut args = &argv[1..];
+            let mut physical = false;
+            while let Some(flag @ ("-L" | "-P")) = args.first().map(|arg| arg.as_str()) {
+                physical = flag == "-P";
+                args = &args[1..];
+            }
+            if args.len() > 1 {
+                println!("tsh: cd: too many arguments");
+                return Some(1);
+            }
+            let mut vars = VARS.lock().unwrap();
+            let (dir, print) = match args.first().map(|arg| arg.as_str()) {
+                None => match vars.get("HOME") {
+                    Some(home) => (home.to_string(), false),
+                    None => {
+                        println!("tsh: cd: HOME not set");
+                        return Some(1);
+                    }
+                },
+                Some("-") => match vars.get("OLDPWD") {
+                    Some(old) => (old.to_string(), true),
+                    None => {
+                        println!("tsh: cd: OLDPWD not set");
+                        return Some(1);
+                    }
+                },
+                Some(dir) => dirs::search(&vars, dir),
+            };
+            match dirs::cd(&mut vars, &dir, physical) {
+                Ok(new) if print => println!("{}", new),
+                Ok(_) => {}
+                Err(e) => {
+                    println!("tsh: cd: {}", e);
+                    return Some(1);
+                }
+            }
+        }
+        "pwd" => match argv.get(1).map(|arg| arg.as_str()) {
+            Some("-P") => println!("{}", dirs::physical()),
+            None | Some("-L") => println!("{}", dirs::pwd(&VARS.lock().unwrap())),
+            Some(arg) => {
+                println!("tsh: pwd: {}: invalid option", arg);
+                return Some(2);
+            }
+        },
+        "pushd" | "popd" => {
+            let mut stack = DIRS.lock().unwrap();
+            let mut vars = VARS.lock().unwrap();
+            let index = argv
+                .get(1)
+                .and_then(|arg| arg.strip_prefix('+'))
+                .and_then(|n| n.parse::<usize>().ok());
+            let res = match (argv[0].as_str(), argv.get(1), index) {
+                ("pushd", None, _) => stack.exchange(&mut vars),
+                ("pushd", Some(_), Some(n)) => stack.rotate(&mut vars, n),
+                ("pushd", Some(dir), None) => stack.push(&mut vars, dir),
+                (_, None, _) => stack.pop(&mut vars, 0),
+                (_, Some(_), Some(n)) => stack.pop(&mut vars, n),
+                (_, Some(arg), None) => Err(format!("{}: invalid argument", arg)),
+            };
+            match res {
+                Ok(_) => print!("{}", stack.list(&vars, false, false, false)),
+                Err(e) => {
+                    println!("tsh: {}: {}", argv[0], e);
+                    return Some(1);
+                }
+            }
+        }
+        "dirs" => {
+            let (mut clear, mut long, mut lines, mut numbered) = (false, false, false, false);
+            for arg in &argv[1..] {
+                let flags = match arg.strip_prefix('-') {
+                    Some(flags) if !flags.is_empty() => flags,
+                    _ => "?",
+                };
+                for flag in flags.chars() {
+                    match flag {
+                        'c' => clear = true,
+                        'l' => long = true,
+                        'p' => lines = true,
+                        'v' => numbered = true,
+                        _ => {
+                            println!("tsh: dirs: {}: invalid option", arg);
+                            return Some(2);
+                        }
+                    }
+                }
+            }
+            let mut stack = DIRS.lock().unwrap();
+            if clear {
+                stack.clear();
             } else {
-                None
-            } {
-                JOBMANAGER.lock().unwrap().set_fg(pid);
-                println!("{}", JOBMANAGER.lock().unwrap().list().trim_end());
-                waitfg();
+                print!(
+                    "{}",
+                    stack.list(&VARS.lock().unwrap(), long, lines, numbered)
+                );
+            }
+        }
+        _ => return None,
+    };
+    Some(0)
+}
+
+/// Finds the job an argument of `fg`, `bg` or `kill` names, either by the
+/// pid of one of its processes or as a `%` job spec
+fn find_job<'a>(manager: &'a mut JobManager, arg: &str) -> Result<&'a mut Job, JobError> {
+    if arg.starts_with('%') {
+        return manager.resolve(arg);
+    }
+    match arg.parse::<i32>() {
+        Ok(pid) => manager.get_pid_mut(Pid::from_raw(pid)),
+        Err(_) => Err(JobError::BadArgument),
+    }
+}
+
+/// Prints why a job lookup of a builtin failed the way tshref does, a job
+/// or process that doesn't exist without the builtin's name
+fn job_error(builtin: &str, e: &JobError) {
+    match e {
+        JobError::NoSuchPid(_) | JobError::NoSuchJid(_) | JobError::NoSuchJob(_) => {
+            println!("{}", e)
+        }
+        _ => println!("{}: {}", builtin, e),
+    }
+}
+
+/// `kill [-s sigspec | -n signum | -sigspec] pid | %jid ...` and
+/// `kill -l [sigspec | status]`
+fn kill(args: &[String]) -> i32 {
+    const USAGE: &str =
+        "tsh: kill: usage: kill [-s sigspec | -n signum | -sigspec] pid | %jid ... \
+                         or kill -l [sigspec]";
+    let (sig, targets) = match args.first().map(|arg| arg.as_str()) {
+        None => {
+            println!("{}", USAGE);
+            return 2;
+        }
+        Some("-l" | "-L") => {
+            if args.len() == 1 {
+                println!("{}", helpers::signal_list());
+                return 0;
+            }
+            let mut status = 0;
+            for arg in &args[1..] {
+                match helpers::parse_signal(arg) {
+                    // an exit status of a signal death names the signal too
+                    Some(Some(sig)) if arg.parse::<i32>().is_ok() => {
+                        println!("{}", helpers::signal_name(sig))
+                    }
+                    Some(Some(sig)) => println!("{}", sig as i32),
+                    _ => match arg
+                        .parse::<i32>()
+                        .ok()
+                        .and_then(|code| Signal::try_from(code - 128).ok())
+                    {
+                        Some(sig) => println!("{}", helpers::signal_name(sig)),
+                        None => {
+                            println!("tsh: kill: {}: invalid signal specification", arg);
+                            status = 1;
+                        }
+                    },
+                }
+            }
+            return status;
+        }
+        Some("-s" | "-n") => match args.get(1) {
+            Some(spec) => (helpers::parse_signal(spec).ok_or(spec.as_str()), &args[2..]),
+            None => {
+                println!("{}", USAGE);
+                return 2;
+            }
+        },
+        Some("--") => (Ok(Some(Signal::SIGTERM)), &args[1..]),
+        Some(spec) if spec.starts_with('-') => (
+            helpers::parse_signal(&spec[1..]).ok_or(&spec[1..]),
+            &args[1..],
+        ),
+        Some(_) => (Ok(Some(Signal::SIGTERM)), args),
+    };
+    let sig = match sig {
+        Ok(sig) => sig,
+        Err(spec) => {
+            println!("tsh: kill: {}: invalid signal specification", spec);
+            return 1;
+        }
+    };
+    if targets.is_empty() {
+        println!("{}", USAGE);
+        return 2;
+    }
+
+    let mut status = 0;
+    for target in targets {
+        let res = if target.starts_with('%') {
+            let mut manager = JOBMANAGER.lock().unwrap();
+            let control = manager.job_control();
+            match find_job(&mut manager, target) {
+                Ok(job) => job.kill(sig, control),
+                Err(e) => {
+                    job_error("kill", &e);
+                    status = 1;
+                    continue;
+                }
+            }
+        } else {
+            match target.parse::<i32>() {
+                Ok(pid) => nix::sys::signal::kill(Pid::from_raw(pid), sig),
+                Err(_) => {
+                    println!(
+                        "tsh: kill: {}: arguments must be process or job IDs",
+                        target
+                    );
+                    status = 1;
+                    continue;
+                }
+            }
+        };
+        if let Err(e) = res {
+            println!("tsh: kill: ({}) - {}", target, e.desc());
+            status = 1;
+        }
+    }
+    status
+}
+
+/// `jobs [-lprsv] [--json] [%job ...]` lists jobs, `-l` with the pid and
+/// status of every process, `-p` only process group ids, `-r` and `-s` only
+/// running or stopped jobs, `-v` with resource usage and `--json` as JSON.
+/// `jobs -x cmd args` runs cmd with job specs in args replaced by pgids.
+fn jobs(args: &[String]) -> i32 {
+    let mut listing = Listing::Short;
+    let (mut running, mut stopped) = (false, false);
+    let mut specs = vec![];
+    for (i, arg) in args.iter().enumerate() {
+        match arg.as_str() {
+            "--json" => listing = Listing::Json,
+            "-x" => return jobs_exec(&args[i + 1..]),
+            flags if flags.len() > 1 && flags.starts_with('-') => {
+                for flag in flags[1..].chars() {
+                    match flag {
+                        'l' => listing = Listing::Long,
+                        'p' => listing = Listing::Pids,
+                        'v' => listing = Listing::Verbose,
+                        'r' => running = true,
+                        's' => stopped = true,
+                        _ => {
+                            println!("tsh: jobs: -{}: invalid option", flag);
+                            println!("jobs: usage: jobs [-lprsv] [--json] [jobspec ...] or jobs -x command [args]");
+                            return 2;
+                        }
+                    }
+                }
+            }
+            spec => specs.push(spec),
+        }
+    }
+
+    let mut manager = JOBMANAGER.lock().unwrap();
+    let mut status = 0;
+    let all = specs.is_empty();
+    let mut pgids = vec![];
+    for spec in specs {
+        match find_job(&mut manager, spec) {
+            Ok(job) => pgids.push(job.pid),
+            Err(e) => {
+                job_error("jobs", &e);
+                status = 1;
+            }
+        }
+    }
+    let jobs: Vec<&Job> = manager
+        .jobs()
+        .filter(|job| all || pgids.contains(&job.pid))
+        .filter(|job| match job.state {
+            States::Stopped(_) => !running || stopped,
+            States::Running { .. } => !stopped || running,
+            _ => !stopped && !running,
+        })
+        .collect();
+    let list: Vec<String> = jobs
+        .iter()
+        .map(|job| match listing {
+            Listing::Short => job.to_string(),
+            Listing::Long => format!("{}\n{}", job, job.processes()),
+            Listing::Pids => job.pid.to_string(),
+            Listing::Verbose => format!("{}\n{}", job, job.usage_line()),
+            Listing::Json => job.json(),
+        })
+        .collect();
+    match listing {
+        Listing::Json => println!("[{}]", list.join(",")),
+        _ => println!("{}", list.join("\n")),
+    }
+    // finished jobs are gone once they were shown
+    let pgids: Vec<Pid> = jobs.iter().map(|job| job.pid).collect();
+    for pgid in pgids {
+        manager.forget(pgid);
+    }
+    WARNED.store(LINES.load(Ordering::SeqCst) + 1, Ordering::SeqCst);
+    status
+}
+
+/// How `jobs` shows each job
+enum Listing {
+    Short,
+    Long,
+    Pids,
+    Verbose,
+    Json,
+}
+
+/// `jobs -x cmd args`
+fn jobs_exec(args: &[String]) -> i32 {
+    if args.is_empty() {
+        println!("tsh: jobs: -x: option requires an argument");
+        return 2;
+    }
+    let mut argv = vec![];
+    {
+        let mut manager = JOBMANAGER.lock().unwrap();
+        for arg in args {
+            if !arg.starts_with('%') {
+                argv.push(arg.clone());
+                continue;
+            }
+            match find_job(&mut manager, arg) {
+                Ok(job) => argv.push(job.pid.to_string()),
+                Err(e) => {
+                    job_error("jobs", &e);
+                    return 1;
+                }
+            }
+        }
+    }
+    if let Some(status) = builtin(&argv) {
+        return status;
+    }
+    let stage = Expanded {
+        assignments: vec![],
+        argv,
+        redirects: vec![],
+    };
+    exec(&stage.argv.join(" "), vec![stage], false);
+    STATUS.load(Ordering::SeqCst)
+}
+
+/// `set` lists the variables, `set -b` or `set -o notify` turns on
+/// reporting finished jobs right away and `+` turns it off again
+fn set(args: &[String]) -> i32 {
+    if args.is_empty() {
+        print!("{}", VARS.lock().unwrap().list());
+        return 0;
+    }
+    let mut args = args.iter().map(|arg| arg.as_str());
+    while let Some(arg) = args.next() {
+        let (on, option) = match arg.split_at_checked(1) {
+            Some(("-", option)) => (true, option),
+            Some(("+", option)) => (false, option),
+            _ => {
+                println!("tsh: set: {}: invalid option", arg);
+                return 2;
+            }
+        };
+        match option {
+            "b" => NOTIFY.store(on, Ordering::SeqCst),
+            "o" => match args.next() {
+                Some("notify") => NOTIFY.store(on, Ordering::SeqCst),
+                Some(name) => {
+                    println!("tsh: set: {}: invalid option name", name);
+                    return 1;
+                }
+                None => {
+                    let notify = NOTIFY.load(Ordering::SeqCst);
+                    match on {
+                        true => println!("{:<15}\t{}", "notify", ["off", "on"][notify as usize]),
+                        false => println!("set {}o notify", ["+", "-"][notify as usize]),
+                    }
+                }
+            },
+            _ => {
+                println!("tsh: set: {}: invalid option", arg);
+                println!("set: usage: set [-b] [-o option-name]");
+                return 2;
             }
         }
+    }
+    0
+}
+
+/// `history [n]` lists the last n lines read, all of them by default, `-c`
+/// clears the history and `-d n` deletes line n
+fn history(args: &[String]) -> i32 {
+    let mut history = HISTORY.lock().unwrap();
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+    match args[..] {
+        [] => print!("{}", history.list(None)),
+        ["-c"] => history.clear(),
+        ["-d", n] => {
+            let res = n
+                .parse()
+                .map_err(|_| format!("{}: history position out of range", n));
+            if let Err(e) = res.and_then(|n| history.delete(n)) {
+                println!("tsh: history: {}", e);
+                return 1;
+            }
+        }
+        ["-d"] => {
+            println!("tsh: history: -d: option requires an argument");
+            return 2;
+        }
+        [option] if option.starts_with('-') && option.len() > 1 => {
+            println!("tsh: history: {}: invalid option", option);
+            println!("history: usage: history [-c] [-d offset] [n]");
+            return 2;
+        }
+        [n] => match n.parse() {
+            Ok(n) => print!("{}", history.list(Some(n))),
+            Err(_) => {
+                println!("tsh: history: {}: numeric argument required", n);
+                return 1;
+            }
+        },
         _ => {
-            exec(line, argv, isbg);
+            println!("tsh: history: too many arguments");
+            return 1;
         }
+    }
+    0
+}
+
+/// Completes the word before the cursor for the line editor
+fn complete(line: &str) -> Completions {
+    let vars = VARS.lock().unwrap();
+    let context = complete::Context {
+        builtins: &BUILTINS,
+        paths: vars.get("PATH").unwrap_or("").to_string(),
+        home: vars.get("HOME").map(str::to_string),
+        variables: vars.names().map(str::to_string).collect(),
+        jobs: JOBMANAGER
+            .lock()
+            .unwrap()
+            .jobs()
+            .map(|job| (job.jid, job.cmd.clone()))
+            .collect(),
     };
+    drop(vars);
+    COMPLETER.lock().unwrap().complete(line, &context)
 }
 
-fn exec(line: &str, argv: Vec<String>, isbg: bool) {
-    let mut mask: SigSet = SigSet::empty();
-    mask.add(Signal::SIGCHLD);
-    match sigprocmask(SigmaskHow::SIG_BLOCK, Some(&mask), None) {
-        Ok(_) => {}
-        Err(_e) => unix_error("Unable to block signal"),
+/// `complete [-cdfjv] [-W words] name ...` sets what the arguments of the
+/// commands complete to, `-r` goes back to files and `complete` or `-p`
+/// prints the specs
+fn complete_builtin(args: &[String]) -> i32 {
+    let mut completer = COMPLETER.lock().unwrap();
+    let (mut specs, mut names): (_, Vec<String>) = (vec![], vec![]);
+    let (mut print, mut remove) = (false, false);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let flags = match arg.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() && names.is_empty() => flags,
+            _ => {
+                names.push(arg.clone());
+                continue;
+            }
+        };
+        for flag in flags.chars() {
+            match flag {
+                'p' => print = true,
+                'r' => remove = true,
+                'c' => specs.push(Spec::Commands),
+                'd' => specs.push(Spec::Directories),
+                'f' => specs.push(Spec::Files),
+                'j' => specs.push(Spec::Jobs),
+                'v' => specs.push(Spec::Variables),
+                'W' => match args.next() {
+                    Some(words) => specs.push(Spec::Words(
+                        words.split_whitespace().map(str::to_string).collect(),
+                    )),
+                    None => {
+                        println!("tsh: complete: -W: option requires an argument");
+                        return 2;
+                    }
+                },
+                _ => {
+                    println!("tsh: complete: -{}: invalid option", flag);
+                    println!("complete: usage: complete [-pr] [-cdfjv] [-W wordlist] [name ...]");
+                    return 2;
+                }
+            }
+        }
+    }
+
+    let mut status = 0;
+    if remove || print || specs.is_empty() {
+        if names.is_empty() && !remove {
+            names = completer.commands().map(str::to_string).collect();
+        }
+        for name in names {
+            let found = match remove {
+                true => completer.remove(&name),
+                false => match completer.get(&name) {
+                    Some(specs) => {
+                        let specs: Vec<String> =
+                            specs.iter().map(|spec| spec.to_string()).collect();
+                        println!("complete {} {}", specs.join(" "), name);
+                        true
+                    }
+                    None => false,
+                },
+            };
+            if !found {
+                println!("tsh: complete: {}: no completion specification", name);
+                status = 1;
+            }
+        }
+        return status;
+    }
+    if names.is_empty() {
+        println!("complete: usage: complete [-pr] [-cdfjv] [-W wordlist] [name ...]");
+        return 2;
+    }
+    for name in names {
+        completer.register(&name, specs.clone());
+    }
+    status
+}
+
+/// `disown [-h] [-a | -r] [%job ...]`: forgets jobs, or with `-h` only keeps
+/// them from getting SIGHUP when the shell exits
+fn disown(args: &[String]) -> i32 {
+    let (mut keep, mut all, mut running) = (false, false, false);
+    let mut specs = vec![];
+    for arg in args {
+        match arg.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() => {
+                for flag in flags.chars() {
+                    match flag {
+                        'h' => keep = true,
+                        'a' => all = true,
+                        'r' => running = true,
+                        _ => {
+                            println!("tsh: disown: -{}: invalid option", flag);
+                            println!("disown: usage: disown [-h] [-ar] [jobspec ...]");
+                            return 2;
+                        }
+                    }
+                }
+            }
+            _ => specs.push(arg.as_str()),
+        }
+    }
+
+    let mut manager = JOBMANAGER.lock().unwrap();
+    let mut pgids = vec![];
+    let mut status = 0;
+    if all || running {
+        pgids.extend(
+            manager
+                .jobs()
+                .filter(|job| !running || matches!(job.state, States::Running { .. }))
+                .map(|job| job.pid),
+        );
+    } else {
+        if specs.is_empty() {
+            specs.push("%+");
+        }
+        for spec in specs {
+            match find_job(&mut manager, spec) {
+                Ok(job) => pgids.push(job.pid),
+                Err(e) => {
+                    job_error("disown", &e);
+                    status = 1;
+                }
+            }
+        }
+    }
+    for pgid in pgids {
+        if keep {
+            if let Ok(job) = manager.get_pid_mut(pgid) {
+                job.nohup = true;
+            }
+        } else {
+            let _ = manager.remove_job(pgid);
+        }
+    }
+    status
+}
+
+/// `wait [-n] [pid | %job ...]`: waits for background jobs to finish or
+/// stop and returns the status of the last one. `-n` waits for whichever
+/// of them finishes next.
+fn wait(args: &[String]) -> i32 {
+    let mut manager = JOBMANAGER.lock().unwrap();
+    INTERRUPTED.store(false, Ordering::SeqCst);
+    let next = args.first().is_some_and(|arg| arg == "-n");
+    let targets = &args[next as usize..];
+
+    if next {
+        let mut status = 127;
+        let mut pids = vec![];
+        for target in targets {
+            match wait_target(&mut manager, target) {
+                // one that finished before is the next one already
+                Ok(pid) if manager.get_pid(pid).is_err() => {
+                    if let Some(status) = manager.take_any_finished(Some(&[pid])) {
+                        return status;
+                    }
+                    println!("tsh: wait: pid {} is not a child of this shell", pid);
+                }
+                Ok(pid) => pids.push(pid),
+                Err(error) => status = error,
+            }
+        }
+        let pids = (!targets.is_empty()).then_some(pids);
+        loop {
+            if let Some(status) = manager.take_any_finished(pids.as_deref()) {
+                return status;
+            }
+            let running = match &pids {
+                Some(pids) => pids.iter().any(|&pid| {
+                    manager
+                        .get_pid(pid)
+                        .is_ok_and(|job| matches!(job.state, States::Running { .. }))
+                }),
+                None => manager.running(),
+            };
+            if !running {
+                return status;
+            }
+            manager = CHANGED.wait(manager).unwrap();
+            if INTERRUPTED.load(Ordering::SeqCst) {
+                return 130;
+            }
+        }
+    }
+
+    if targets.is_empty() {
+        while manager.running() {
+            manager = CHANGED.wait(manager).unwrap();
+            if INTERRUPTED.load(Ordering::SeqCst) {
+                return 130;
+            }
+        }
+        manager.forget_finished();
+        return 0;
+    }
+
+    let mut status = 0;
+    for target in targets {
+        let pid = match wait_target(&mut manager, target) {
+            Ok(pid) => pid,
+            Err(error) => {
+                status = error;
+                continue;
+            }
+        };
+        status = loop {
+            if let Some(status) = manager.take_finished(pid) {
+                break status;
+            }
+            match manager.get_pid(pid) {
+                Ok(job) if job.state.finished() => break job.status().unwrap_or(0),
+                Ok(&Job {
+                    state: States::Stopped(signal),
+                    ..
+                }) => break 128 + signal,
+                Ok(_) => {}
+                Err(_) => {
+                    println!("tsh: wait: pid {} is not a child of this shell", pid);
+                    break 127;
+                }
+            }
+            manager = CHANGED.wait(manager).unwrap();
+            if INTERRUPTED.load(Ordering::SeqCst) {
+                return 130;
+            }
+        };
+        manager.forget(pid);
+    }
+    status
+}
+
+/// The pid of the job or process a target of `wait` names, or the status
+/// `wait` returns for it after saying why it names none
+fn wait_target(manager: &mut JobManager, target: &str) -> Result<Pid, i32> {
+    let Some(spec) = target.strip_prefix('%') else {
+        return match target.parse::<i32>() {
+            Ok(pid) => Ok(Pid::from_raw(pid)),
+            Err(_) => {
+                println!("tsh: wait: `{}': not a pid or valid job spec", target);
+                Err(2)
+            }
+        };
+    };
+    match manager.resolve(target) {
+        Ok(job) => Ok(job.pid),
+        Err(e) => {
+            // a job that already finished is only known by its jid
+            let jid = spec.parse::<u32>().ok();
+            match jid.and_then(|jid| manager.finished_pid(jid)) {
+                Some(pid) => Ok(pid),
+                None => {
+                    job_error("wait", &e);
+                    Err(127)
+                }
+            }
+        }
+    }
+}
+
+/// The current `$PATH`
+fn paths() -> String {
+    VARS.lock().unwrap().get("PATH").unwrap_or("").to_string()
+}
+
+/// Forks every stage of the pipeline into one process group, connecting
+/// each stage's stdout to the next stage's stdin
+fn exec(line: &str, stages: Vec<Expanded>, isbg: bool) {
+    let mut redirections = vec![];
+    for stage in &stages {
+        match Redirections::open(&stage.redirects) {
+            Ok(opened) => redirections.push(opened),
+            Err(e) => {
+                println!("tsh: {}", e);
+                STATUS.store(1, Ordering::SeqCst);
+                return;
+            }
+        }
+    }
+
+    let control = JOBMANAGER.lock().unwrap().job_control();
+    let tty = TERMINAL.lock().unwrap().is_some();
+    let forking = FORKING.lock().unwrap();
+
+    let mut procs: Vec<Process> = vec![];
+    let mut pgid = Pid::from_raw(0);
+    let mut input: Option<OwnedFd> = None;
+    let paths: Vec<Option<PathBuf>> = {
+        let paths = paths();
+        let mut hash = HASH.lock().unwrap();
+        stages
+            .iter()
+            .map(|stage| match stage.argv.first() {
+                Some(name) if !BUILTINS.contains(&name.as_str()) => hash.lookup(name, &paths),
+                _ => None,
+            })
+            .collect()
+    };
+    let envs: Vec<Vec<String>> = {
+        let vars = VARS.lock().unwrap();
+        stages
+            .iter()
+            .map(|stage| vars.environ(&stage.assignments))
+            .collect()
+    };
+
+    for (i, (((stage, redirections), path), env)) in stages
+        .iter()
+        .zip(&redirections)
+        .zip(&paths)
+        .zip(&envs)
+        .enumerate()
+    {
+        let pipe = if i + 1 < stages.len() {
+            match pipe2(OFlag::O_CLOEXEC) {
+                Ok(pipe) => Some(pipe),
+                Err(_e) => unix_error("Cannot create pipe"),
+            }
+        } else {
+            None
+        };
+
+        let res = match unsafe { fork() } {
+            Ok(res) => res,
+            Err(_e) => unix_error("Cannot fork"),
+        };
+
+        match res {
+            ForkResult::Parent { child } => {
+                if i == 0 {
+                    pgid = child;
+                }
+                // also done by the child, whichever runs first wins the race
+                if control {
+                    let _ = setpgid(child, pgid);
+                }
+                if tty && !isbg {
+                    if let Some(terminal) = TERMINAL.lock().unwrap().as_ref() {
+                        terminal.give(pgid, None);
+                    }
+                }
+                procs.push(Process::new(child));
+                input = pipe.map(|(read, _write)| read);
+            }
+            ForkResult::Child => {
+                if control {
+                    let _ = setpgid(Pid::from_raw(0), pgid);
+                }
+                if tty && !isbg {
+                    // SIGTTOU is still ignored, so this works from the
+                    // background before the parent gets to it
+                    let pgid = if i == 0 { getpid() } else { pgid };
+                    let _ = tcsetpgrp(stdin(), pgid);
+                }
+                // signals must not reach the shell's event loop through the
+                // inherited handler
+                for sig in [
+                    Signal::SIGCHLD,
+                    Signal::SIGINT,
+                    Signal::SIGTSTP,
+                    Signal::SIGQUIT,
+                    Signal::SIGTTIN,
+                    Signal::SIGTTOU,
+                ] {
+                    if tty || !matches!(sig, Signal::SIGTTIN | Signal::SIGTTOU) {
+                        let _ = unsafe { signal(sig, SigHandler::SigDfl) };
+                    }
+                }
+                if !control && isbg {
+                    // background jobs of a script must not be stopped by ^C
+                    unsafe {
+                        let _ = signal(Signal::SIGINT, SigHandler::SigIgn);
+                        let _ = signal(Signal::SIGQUIT, SigHandler::SigIgn);
+                    }
+                }
+                if let Some(read) = &input {
+                    dup2(read.as_raw_fd(), STDIN_FILENO).unwrap();
+                }
+                if let Some((_read, write)) = &pipe {
+                    dup2(write.as_raw_fd(), STDOUT_FILENO).unwrap();
+                }
+                if let Err(e) = redirections.apply() {
+                    println!("tsh: {}", e);
+                    exit(1);
+                }
+                if stage.argv.is_empty() {
+                    exit(0);
+                }
+                if stages.len() > 1 {
+                    if let Some(status) = builtin(&stage.argv) {
+                        let _ = stdout().flush();
+                        exit(status);
+                    }
+                }
+                execute(path.clone(), &stage.argv, env);
+            }
+        }
+    }
+
+    if isbg {
+        let last = procs.last().unwrap().pid;
+        let job = Job::new(procs, States::Running { fg: false }, line.to_string());
+        let jid = match JOBMANAGER.lock().unwrap().add_job(job) {
+            Ok(jid) => jid,
+            Err(e) => {
+                println!("tsh: {}", e);
+                return;
+            }
+        };
+        VARS.lock().unwrap().set_last_background(last.as_raw());
+        if control {
+            println!("[{}] ({}) {}", jid, pgid.as_raw(), line.trim_end());
+        }
+        // a job that finished already is reported after this line
+        drop(forking);
+        STATUS.store(0, Ordering::SeqCst);
+    } else {
+        let job = Job::new(procs, States::Running { fg: true }, line.to_string());
+        if let Err(e) = JOBMANAGER.lock().unwrap().add_job(job) {
+            println!("tsh: {}", e);
+            return;
+        }
+        drop(forking);
+        waitfg(pgid);
+    }
+}
+
+/// Replaces the current (child) process with argv
+fn execute(path: Option<PathBuf>, argv: &[String], env: &[String]) -> ! {
+    let path = match path {
+        Some(path) => path,
+        None => {
+            println!("{}: Command not found", argv[0]);
+            exit(127);
+        }
     };
     // prepare argc and argv
-    let argct = CString::new(argv[0].clone()).unwrap();
+    let argct = CString::new(path.into_os_string().into_vec()).unwrap();
     let argcc = argct.as_c_str();
     let argvt: Vec<CString> = argv
-        .clone()
-        .into_iter()
+        .iter()
         .map(|s| CString::new(s.clone()).unwrap())
         .collect();
     let mut argvc: Vec<&CStr> = vec![];
     for arg in argvt.iter() {
         argvc.push(arg.as_c_str());
     }
-    let res = match unsafe { fork() } {
-        Ok(res) => res,
-        Err(_e) => unix_error("Cannot fork"),
-    };
-
-    match res {
-        ForkResult::Parent { child } => {
-            if isbg {
-                let jid = {
-                    let mut manager = JOBMANAGER.lock().unwrap();
-                    manager
-                        .add_job(Job::new(child, States::BG, line.to_string()))
-                        .unwrap()
-                };
-                sigprocmask(SigmaskHow::SIG_UNBLOCK, Some(&mask), None).unwrap();
-                println!("[{}] ({}) {}", jid, child.as_raw(), line.trim_end());
-            } else {
-                {
-                    let mut manager = JOBMANAGER.lock().unwrap();
-                    manager
-                        .add_job(Job::new(child, States::FG, line.to_string()))
-                        .unwrap();
-                }
-                sigprocmask(SigmaskHow::SIG_UNBLOCK, Some(&mask), None).unwrap();
-                waitfg();
+    let envt: Vec<CString> = env
+        .iter()
+        .map(|s| CString::new(s.clone()).unwrap())
+        .collect();
+    match execve(argcc, argvc.as_slice(), envt.as_slice()) {
+        Ok(_) => unreachable!(),
+        Err(e) => {
+            if e == Errno::ENOENT {
+                println!("{}: Command not found", argv[0]);
+                exit(127);
             }
-        }
-        ForkResult::Child => {
-            setpgid(Pid::from_raw(0), Pid::from_raw(0)).unwrap();
-            match execv(argcc, argvc.as_slice()) {
-                Ok(_) => {}
-                Err(e) => {
-                    if e == Errno::ENOENT {
-                        println!("{}: Command not found", argv[0]);
-                        exit(0);
-                    }
-                    unix_error("Execv Error");
+            if e == Errno::ENOEXEC {
+                // a script without a `#!` line is run by tsh itself
+                if let Ok(exe) = std::env::current_exe() {
+                    let exe = CString::new(exe.into_os_string().into_vec()).unwrap();
+                    let mut args = vec![exe.as_c_str(), argcc];
+                    args.extend(&argvc[1..]);
+                    let _ = execve(exe.as_c_str(), args.as_slice(), envt.as_slice());
                 }
-            };
+            }
+            unix_error("Execv Error");
         }
     }
 }
 
 impl Job {
-    fn bg(&mut self) {
-        match kill(self.pid, Signal::SIGCONT) {
+    /// Sends sig to every process of the job. A stopped job is continued
+    /// so it can act on SIGTERM or SIGHUP.
+    fn kill(&self, sig: Option<Signal>, control: bool) -> nix::Result<()> {
+        let send = |sig: Option<Signal>| match control {
+            true => killpg(self.pid, sig),
+            // without job control the job shares the shell's process group
+            false => self
+                .procs
+                .iter()
+                .filter(|proc| proc.status.is_none())
+                .try_for_each(|proc| nix::sys::signal::kill(proc.pid, sig)),
+        };
+        send(sig)?;
+        if matches!(self.state, States::Stopped(_))
+            && matches!(sig, Some(Signal::SIGTERM | Signal::SIGHUP))
+        {
+            send(Some(Signal::SIGCONT))?;
+        }
+        Ok(())
+    }
+
+    fn bg(&mut self) -> Result<(), JobError> {
+        self.transition(States::Running { fg: false })?;
+        match killpg(self.pid, Signal::SIGCONT) {
             Ok(_) => {}
             Err(_) => unix_error("Send SIGCONT failed"),
         };
-        self.state = States::BG;
 
         log!("Backgrounding job id: {}", self.jid);
 
         println!("[{}] ({}) {}", self.jid, self.pid, self.cmd);
+        Ok(())
     }
 
-    fn fg(&mut self) {
-        let mut mask: SigSet = SigSet::empty();
-        mask.add(Signal::SIGCHLD);
-        match sigprocmask(SigmaskHow::SIG_BLOCK, Some(&mask), None) {
-            Ok(_) => {}
-            Err(_e) => unix_error("Unable to block signal"),
-        };
-
-        match kill(self.pid, Signal::SIGCONT) {
+    fn fg(&mut self) -> Result<(), JobError> {
+        self.transition(States::Running { fg: true })?;
+        if let Some(terminal) = TERMINAL.lock().unwrap().as_ref() {
+            terminal.give(self.pid, self.tmodes.as_ref());
+        }
+        match killpg(self.pid, Signal::SIGCONT) {
             Ok(_) => {}
             Err(_) => unix_error("Send SIGCONT failed"),
         };
 
-        self.state = States::FG;
-
-        match sigprocmask(SigmaskHow::SIG_UNBLOCK, Some(&mask), None) {
-            Ok(_) => {}
-            Err(_e) => unix_error("Unable to unblock signal"),
-        };
-
         log!("Forgrounding job id: {}", self.jid);
+        Ok(())
     }
 }
 
-fn waitfg() {
+/// Waits until the foreground job pgid finishes or stops, then takes the
+/// terminal back from it
+fn waitfg(pgid: Pid) {
     LOCK.1.lock().unwrap().recv().unwrap();
+    let modes = match TERMINAL.lock().unwrap().as_ref() {
+        Some(terminal) => terminal.reclaim(),
+        None => return,
+    };
+    if let Ok(job) = JOBMANAGER.lock().unwrap().get_pid_mut(pgid) {
+        job.tmodes = modes;
+    }
 }
 
-extern "C" fn sigstp_handler(sigstp: sig_t) {
-    log!("Received SIGTSTP");
-    MESSAGES
-        .0
-        .lock()
-        .unwrap()
-        .send(MessageQueue::Signal { signal: sigstp })
-        .unwrap();
-}
-
-extern "C" fn sigint_handler(sigquit: sig_t) {
-    log!("Received SIGINT");
-    MESSAGES
-        .0
-        .lock()
-        .unwrap()
-        .send(MessageQueue::Signal { signal: sigquit })
-        .unwrap();
+/// Handles every signal the shell catches by writing its number to the
+/// self-pipe, which is all that is async-signal-safe to do here
+extern "C" fn signal_handler(sig: sig_t) {
+    let errno = Errno::last_raw();
+    let byte = sig as u8;
+    unsafe {
+        libc::write(
+            SIGNALS.load(Ordering::Relaxed),
+            &byte as *const u8 as *const libc::c_void,
+            1,
+        );
+    }
+    Errno::set_raw(errno);
 }
 
-extern "C" fn sigchld_handler(_sigchld: sig_t) {
-    log!("Received SIGCHLD");
-    let mut flag = WaitPidFlag::empty();
-    flag.set(WaitPidFlag::WNOHANG, true);
-    flag.set(WaitPidFlag::WUNTRACED, true);
+/// Acts on the signals reported through the self-pipe. This is the only
+/// place children are reaped and their jobs updated.
+fn event_loop(events: OwnedFd) {
+    log!("Event loop started");
+    let mut events = File::from(events);
+    let mut buf = [0u8; 64];
     loop {
-        let res = match waitpid(None, Some(flag)) {
-            Ok(res) => res,
-            Err(_e) => {
-                if let Errno::ECHILD = _e {
-                    break;
-                }
-                unix_error("WaitPid Error")
-            }
+        let len = match events.read(&mut buf) {
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_e) => unix_error("Reading signals failed"),
         };
-
-        log!("Waitpid returned: {:?}", res);
-
-        match res {
-            WaitStatus::Stopped(pid, signal) => {
-                log!("Handling stopped job");
-                MESSAGES
-                    .0
-                    .lock()
-                    .unwrap()
-                    .send(MessageQueue::Stopped {
-                        pid,
-                        signal: signal as i32,
-                    })
-                    .unwrap();
-            }
-            WaitStatus::Signaled(pid, signal, _core_dumped) => {
-                log!("Handling signaled job");
-                MESSAGES
-                    .0
-                    .lock()
-                    .unwrap()
-                    .send(MessageQueue::Signaled {
-                        pid,
-                        signal: signal as i32,
-                    })
-                    .unwrap();
-            }
-            WaitStatus::Exited(pid, _exitcode) => {
-                log!("Handling exited job");
-                MESSAGES
-                    .0
-                    .lock()
-                    .unwrap()
-                    .send(MessageQueue::RemoveJob { pid })
-                    .unwrap();
-            }
-            WaitStatus::StillAlive => {
-                break;
+        let _forking = FORKING.lock().unwrap();
+        for &byte in &buf[..len] {
+            log!("Received signal {}", byte);
+            match Signal::try_from(byte as sig_t) {
+                Ok(Signal::SIGCHLD) => reap(),
+                Ok(Signal::SIGQUIT) => {
+                    println!("Terminating after receipt of SIGQUIT signal");
+                    exit(0)
+                }
+                Ok(Signal::SIGHUP) => {
+                    // the terminal is gone, take the jobs down with us
+                    hangup(&JOBMANAGER.lock().unwrap());
+                    if *EDITING {
+                        HISTORY.lock().unwrap().save(&VARS.lock().unwrap());
+                    }
+                    exit(128 + Signal::SIGHUP as i32)
+                }
+                Ok(signal) => {
+                    // SIGINT and SIGTSTP go to the foreground job
+                    let mut manager = JOBMANAGER.lock().unwrap();
+                    match manager.current() {
+                        Some(pid) => {
+                            let _ = killpg(pid, signal);
+                        }
+                        None if signal == Signal::SIGINT => {
+                            INTERRUPTED.store(true, Ordering::SeqCst);
+                            CHANGED.notify_all();
+                        }
+                        None => {}
+                    }
+                }
+                Err(_) => {}
             }
-            _ => {}
         }
-
-        log!("Message sent");
     }
 }
 
-fn receiver() {
-    log!("Receiver started");
+/// Collects every child that exited, was killed or stopped
+fn reap() {
+    let flags = WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED;
     loop {
-        let message = MESSAGES.1.lock().unwrap().recv().unwrap();
-        log!("Message received: {:?}", message);
-        let fgpid = match JOBMANAGER.lock().unwrap().current() {
-            Some(pid) => pid.as_raw(),
-            None => -1,
+        // waitpid with the resource usage of the child
+        let (mut status, mut rusage) = (0, unsafe { std::mem::zeroed::<libc::rusage>() });
+        let pid = unsafe { libc::wait4(-1, &mut status, flags.bits(), &mut rusage) };
+        let res = match pid {
+            0 => break,
+            -1 if Errno::last() == Errno::ECHILD => break,
+            -1 => unix_error("WaitPid Error"),
+            pid => match WaitStatus::from_raw(Pid::from_raw(pid), status) {
+                Ok(res) => res,
+                Err(_e) => unix_error("WaitPid Error"),
+            },
         };
-        log!("Current FG: {}", fgpid);
-        match message {
-            MessageQueue::RemoveJob { pid } => {
-                JOBMANAGER.lock().unwrap().remove_job(pid).unwrap();
-                match fgpid {
-                    -1 => {}
-                    _ => LOCK.0.lock().unwrap().send(()).unwrap(),
-                }
-            }
-            MessageQueue::Stopped { pid, signal } => {
-                JOBMANAGER
-                    .lock()
-                    .unwrap()
-                    .set_state(pid, States::ST)
-                    .unwrap();
-                println!(
-                    "Job [{}] ({}) stopped by signal {}",
-                    { JOBMANAGER.lock().unwrap().get_pid(pid).unwrap().jid },
-                    pid,
-                    signal
-                );
-                match fgpid {
-                    -1 => {}
-                    _ => LOCK.0.lock().unwrap().send(()).unwrap(),
-                }
+        log!("Waitpid returned: {:?}", res);
+        let fg = JOBMANAGER.lock().unwrap().current();
+        let done = match res {
+            WaitStatus::Exited(pid, code) => finish(pid, Status::Exited(code), &rusage),
+            WaitStatus::Signaled(pid, signal, core_dumped) => {
+                finish(pid, Status::Signaled(signal as i32, core_dumped), &rusage)
             }
-            MessageQueue::Signaled { pid, signal } => {
-                JOBMANAGER.lock().unwrap().remove_job(pid).unwrap();
+            WaitStatus::Stopped(pid, signal) => stop(pid, signal as i32),
+            _ => None,
+        };
+        if done.is_some() && done == fg.map(|pid| pid.as_raw()) {
+            LOCK.0.lock().unwrap().send(()).unwrap();
+        }
+    }
+}
 
-                println!(
-                    "Job [{}] ({}) terminated by signal {}",
-                    { JOBMANAGER.lock().unwrap().get_pid(pid).unwrap().jid },
-                    pid,
-                    signal
-                );
-                match fgpid {
-                    -1 => {}
-                    _ => LOCK.0.lock().unwrap().send(()).unwrap(),
-                }
-            }
-            MessageQueue::Signal { signal } => {
-                if fgpid != -1 {
-                    kill(Pid::from_raw(-fgpid), Signal::try_from(signal).unwrap()).unwrap();
+/// Marks the job of pid as stopped and returns its process group id, unless
+/// it was stopped already
+fn stop(pid: Pid, signal: i32) -> Option<i32> {
+    let mut manager = JOBMANAGER.lock().unwrap();
+    let fg = manager.current();
+    let (jid, pgid) = match manager.get_pid(pid) {
+        Ok(job) => (job.jid, job.pid),
+        _ => return None,
+    };
+    // fails for another process of an already stopped pipeline
+    manager.set_state(pgid, States::Stopped(signal)).ok()?;
+    EDITOR.print(&format!(
+        "Job [{}] ({}) stopped by signal {}",
+        jid, pgid, signal
+    ));
+    if fg == Some(pgid) {
+        STATUS.store(128 + signal, Ordering::SeqCst);
+    }
+    CHANGED.notify_all();
+    Some(pgid.as_raw())
+}
+
+/// Records that pid has exited or was killed by a signal. Once every process
+/// of its job is gone the job is finished and its process group id returned.
+/// A finished background job stays in the job table until it is reported.
+fn finish(pid: Pid, status: Status, rusage: &libc::rusage) -> Option<i32> {
+    let mut manager = JOBMANAGER.lock().unwrap();
+    let fg = manager.current();
+    let job = manager.get_pid_mut(pid).ok()?;
+    if !job.reap(pid, status, rusage) {
+        return None;
+    }
+    let (jid, pgid, usage) = (job.jid, job.pid, job.usage);
+    let (state, status) = (job.outcome()?, job.status()?);
+    let pids = job.procs.iter().map(|proc| proc.pid).collect();
+    manager.set_state(pgid, state).ok()?;
+    if let States::Signaled(signal, _) = state {
+        EDITOR.print(&format!(
+            "Job [{}] ({}) terminated by signal {}",
+            jid, pgid, signal
+        ));
+    }
+    if fg == Some(pgid) {
+        STATUS.store(status, Ordering::SeqCst);
+        *USAGE.lock().unwrap() = usage;
+        let _ = manager.remove_job(pgid);
+    } else {
+        manager.record(jid, pids, status);
+        // a killed job was announced above, but `jobs` can still show it
+        // until the next command line
+        let announced = matches!(state, States::Signaled(..));
+        if manager.job_control() && !announced {
+            if let Some(notice) = manager.notice(pgid) {
+                match NOTIFY.load(Ordering::SeqCst) {
+                    true => {
+                        EDITOR.print(&notice.to_string());
+                        let _ = manager.remove_job(pgid);
+                    }
+                    false => manager.push_notice(notice),
                 }
             }
         }
     }
+    CHANGED.notify_all();
+    Some(pgid.as_raw())
 }
@@ -0,0 +1,218 @@
+use std::{iter::Peekable, vec::IntoIter};
+
+use crate::{
+    ast::{Command, Connector, Item, List, Pipeline, Redirect, WordPart},
+    lexer::{Lexer, ParseError, Token, TokenKind},
+};
+
+/// Parses a line into a list of pipelines.
+///
+/// ```text
+/// list     := pipeline ((';' | '&' | '&&' | '||' | newline) pipeline)* [';' | '&']
+/// pipeline := command ('|' command)*
+/// command  := (assignment | redirect)* (word | redirect)*, not empty
+/// redirect := ('<' | '>' | '>>' | '&>') word | '>&' fd
+/// ```
+pub fn parse(src: &str) -> Result<List, ParseError> {
+    let tokens = Lexer::new(src).tokens()?;
+    Parser {
+        src,
+        tokens: tokens.into_iter().peekable(),
+        end: 0,
+    }
+    .list()
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    tokens: Peekable<IntoIter<Token>>,
+    /// End of the last token consumed
+    end: usize,
+}
+
+impl Parser<'_> {
+    fn list(&mut self) -> Result<List, ParseError> {
+        let mut list = List::default();
+        loop {
+            self.skip_newlines();
+            if self.tokens.peek().is_none() {
+                return Ok(list);
+            }
+            let pipeline = self.pipeline()?;
+            let connector = match self.tokens.next() {
+                None => Connector::Seq,
+                Some(token) => match token.kind {
+                    TokenKind::Semi | TokenKind::Newline => Connector::Seq,
+                    // the times would be reported long after the line ran
+                    TokenKind::Amp if pipeline.timed => {
+                        return Err(ParseError::new(
+                            self.src,
+                            token.span.start,
+                            "time: can't time a background job".to_string(),
+                        ))
+                    }
+                    TokenKind::Amp => Connector::Background,
+                    TokenKind::And => Connector::And,
+                    TokenKind::Or => Connector::Or,
+                    _ => return Err(self.unexpected(Some(token))),
+                },
+            };
+            if let Connector::And | Connector::Or = connector {
+                self.skip_newlines();
+                if self.tokens.peek().is_none() {
+                    return Err(self.end_of_input());
+                }
+            }
+            list.items.push(Item {
+                pipeline,
+                connector,
+            });
+        }
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let timed = self
+            .tokens
+            .next_if(|token| matches!(&token.kind, TokenKind::Word(word) if word.is("time")))
+            .is_some();
+        let start = self.pos();
+        let alone = self.tokens.peek().is_none_or(|token| {
+            matches!(
+                token.kind,
+                TokenKind::Semi
+                    | TokenKind::Newline
+                    | TokenKind::Amp
+                    | TokenKind::And
+                    | TokenKind::Or
+            )
+        });
+        if timed && alone {
+            // `time` alone times an empty command
+            return Ok(Pipeline {
+                commands: vec![Command::default()],
+                span: start..start,
+                timed,
+            });
+        }
+        let mut commands = vec![self.command()?];
+        let mut end = self.end;
+        while self
+            .tokens
+            .next_if(|token| matches!(token.kind, TokenKind::Pipe))
+            .is_some()
+        {
+            self.skip_newlines();
+            commands.push(self.command()?);
+            end = self.end;
+        }
+        Ok(Pipeline {
+            commands,
+            span: start..end,
+            timed,
+        })
+    }
+
+    fn command(&mut self) -> Result<Command, ParseError> {
+        let mut command = Command::default();
+        while let Some(token) = self.tokens.next_if(|token| {
+            !matches!(
+                token.kind,
+                TokenKind::Pipe
+                    | TokenKind::And
+                    | TokenKind::Or
+                    | TokenKind::Semi
+                    | TokenKind::Amp
+                    | TokenKind::Newline
+            )
+        }) {
+            self.end = token.span.end;
+            let redirect = match token.kind {
+                TokenKind::Word(word) => {
+                    command.words.push(word);
+                    continue;
+                }
+                TokenKind::Assignment(name, value) if command.words.is_empty() => {
+                    command.assignments.push((name, value));
+                    continue;
+                }
+                // only assignments before the command name count
+                TokenKind::Assignment(name, mut value) => {
+                    value
+                        .parts
+                        .insert(0, WordPart::Literal(format!("{}=", name)));
+                    command.words.push(value);
+                    continue;
+                }
+                TokenKind::Dup(fd, target) => {
+                    command.redirects.push(Redirect::Dup(fd, target));
+                    continue;
+                }
+                kind => kind,
+            };
+            let target = match self.tokens.next() {
+                Some(Token {
+                    kind: TokenKind::Word(word),
+                    span,
+                }) => {
+                    self.end = span.end;
+                    word
+                }
+                token => return Err(self.unexpected(token)),
+            };
+            command.redirects.push(match redirect {
+                TokenKind::Read(fd) => Redirect::Read(fd, target),
+                TokenKind::Write(fd) => Redirect::Write(fd, target),
+                TokenKind::Append(fd) => Redirect::Append(fd, target),
+                _ => Redirect::WriteAll(target),
+            });
+        }
+        if command.words.is_empty()
+            && command.redirects.is_empty()
+            && command.assignments.is_empty()
+        {
+            return Err(match self.tokens.next() {
+                None => self.end_of_input(),
+                token => self.unexpected(token),
+            });
+        }
+        Ok(command)
+    }
+
+    fn skip_newlines(&mut self) {
+        while self
+            .tokens
+            .next_if(|token| matches!(token.kind, TokenKind::Newline))
+            .is_some()
+        {}
+    }
+
+    fn pos(&mut self) -> usize {
+        match self.tokens.peek() {
+            Some(token) => token.span.start,
+            None => self.src.len(),
+        }
+    }
+
+    /// The line ends where a command has to follow, after `|`, `&&` or `||`
+    fn end_of_input(&self) -> ParseError {
+        ParseError {
+            incomplete: true,
+            ..self.unexpected(None)
+        }
+    }
+
+    fn unexpected(&self, token: Option<Token>) -> ParseError {
+        match token {
+            Some(token) => ParseError::new(
+                self.src,
+                token.span.start,
+                format!("syntax error near unexpected token `{}'", token.kind),
+            ),
+            None => ParseError::new(
+                self.src,
+                self.src.trim_end().len(),
+                "syntax error near unexpected token `newline'".to_string(),
+            ),
+        }
+    }
+}
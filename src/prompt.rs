@@ -0,0 +1,146 @@
+use std::ffi::{CStr, CString};
+
+use nix::{
+    libc,
+    unistd::{geteuid, getuid, User},
+};
+
+use crate::{dirs, vars::Variables};
+
+/// What the escapes of a prompt show
+#[derive(Debug)]
+pub struct Context<'a> {
+    pub vars: &'a Variables,
+    /// `$?`
+    pub status: i32,
+    pub jobs: usize,
+    pub running: usize,
+    pub stopped: usize,
+}
+
+/// Expands the escapes of a `$PS1` or `$PS2` value:
+///
+/// ```text
+/// \u  user            \w  $PWD, $HOME as ~    \t  time, 24 hour HH:MM:SS
+/// \h  host to the .   \W  last part of $PWD   \T  time, 12 hour HH:MM:SS
+/// \H  host            \$  # for root, else $  \@  time, 12 hour am/pm
+/// \s  shell name      \?  exit status         \A  time, 24 hour HH:MM
+/// \j  jobs            \R  running jobs        \d  date, like Tue May 26
+/// \S  stopped jobs    \D{format}  strftime    \n  newline
+/// \e  escape          \a  bell                \nnn  octal character
+/// \[  \]  around characters that take no room, like colors
+/// ```
+///
+/// `\[` and `\]` become `\x01` and `\x02`, which the line editor leaves out
+/// when it measures the prompt.
+pub fn render(template: &str, context: &Context) -> String {
+    let vars = context.vars;
+    let mut res = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('u') => res.push_str(&user(vars)),
+            Some('h') => res.push_str(hostname().split('.').next().unwrap_or_default()),
+            Some('H') => res.push_str(&hostname()),
+            Some('s') => res.push_str("tsh"),
+            Some('w') => res.push_str(&dirs::abbreviate(vars, &dirs::pwd(vars))),
+            Some('W') => {
+                let pwd = dirs::abbreviate(vars, &dirs::pwd(vars));
+                match pwd.rsplit('/').next() {
+                    Some("") | None => res.push_str(&pwd),
+                    Some(last) => res.push_str(last),
+                }
+            }
+            Some('$') => res.push(if geteuid().is_root() { '#' } else { '$' }),
+            Some('?') => res.push_str(&context.status.to_string()),
+            Some('j') => res.push_str(&context.jobs.to_string()),
+            Some('R') => res.push_str(&context.running.to_string()),
+            Some('S') => res.push_str(&context.stopped.to_string()),
+            Some('t') => res.push_str(&strftime("%H:%M:%S")),
+            Some('T') => res.push_str(&strftime("%I:%M:%S")),
+            Some('@') => res.push_str(&strftime("%I:%M %p")),
+            Some('A') => res.push_str(&strftime("%H:%M")),
+            Some('d') => res.push_str(&strftime("%a %b %d")),
+            Some('D') if chars.peek() == Some(&'{') => {
+                chars.next();
+                let format: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                match format.is_empty() {
+                    true => res.push_str(&strftime("%X")),
+                    false => res.push_str(&strftime(&format)),
+                }
+            }
+            Some('n') => res.push('\n'),
+            Some('e') => res.push('\x1b'),
+            Some('a') => res.push('\x07'),
+            Some('[') => res.push('\x01'),
+            Some(']') => res.push('\x02'),
+            Some('\\') => res.push('\\'),
+            Some(digit @ '0'..='7') => {
+                let mut code = digit.to_digit(8).unwrap_or_default();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            code = code * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                res.extend(char::from_u32(code));
+            }
+            Some(c) => {
+                res.push('\\');
+                res.push(c);
+            }
+            None => res.push('\\'),
+        }
+    }
+    res
+}
+
+/// The prompt as it is written to a terminal that isn't edited on
+pub fn strip(prompt: &str) -> String {
+    prompt.replace(['\x01', '\x02'], "")
+}
+
+fn user(vars: &Variables) -> String {
+    match User::from_uid(getuid()) {
+        Ok(Some(user)) => user.name,
+        _ => vars.get("USER").unwrap_or_default().to_string(),
+    }
+}
+
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    match unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } {
+        0 => match CStr::from_bytes_until_nul(&buf) {
+            Ok(name) => name.to_string_lossy().into_owned(),
+            Err(_) => String::new(),
+        },
+        _ => String::new(),
+    }
+}
+
+/// The local time in format
+fn strftime(format: &str) -> String {
+    let Ok(format) = CString::new(format) else {
+        return String::new();
+    };
+    let mut buf = [0u8; 256];
+    let len = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm = std::mem::zeroed::<libc::tm>();
+        libc::localtime_r(&now, &mut tm);
+        libc::strftime(
+            buf.as_mut_ptr() as *mut libc::c_char,
+            buf.len(),
+            format.as_ptr(),
+            &tm,
+        )
+    };
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
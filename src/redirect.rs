@@ -0,0 +1,151 @@
+use std::{
+    ffi::CStr,
+    fs::{File, OpenOptions},
+    io,
+    os::fd::{AsRawFd, OwnedFd, RawFd},
+};
+
+use nix::{
+    errno::Errno,
+    libc::{self, STDERR_FILENO, STDOUT_FILENO},
+    unistd::{close, dup, dup2},
+};
+
+#[derive(Debug)]
+pub enum Redirect {
+    /// `< file`
+    Read(RawFd, String),
+    /// `> file`, `2> file`
+    Write(RawFd, String),
+    /// `>> file`, `2>> file`
+    Append(RawFd, String),
+    /// `&> file`
+    WriteAll(String),
+    /// `2>&1`
+    Dup(RawFd, RawFd),
+}
+
+/// A redirection whose file has already been opened
+#[derive(Debug)]
+enum Opened {
+    File(RawFd, OwnedFd),
+    Dup(RawFd, RawFd),
+}
+
+/// The redirections of one command, opened by the shell before it forks so
+/// a missing file is reported without creating a job
+#[derive(Debug, Default)]
+pub struct Redirections {
+    opened: Vec<Opened>,
+}
+
+/// File descriptors saved before a builtin's redirections were applied,
+/// None for the ones that weren't open
+pub struct Saved {
+    saved: Vec<(RawFd, Option<RawFd>)>,
+}
+
+impl Redirections {
+    pub fn open(redirects: &[Redirect]) -> Result<Self, String> {
+        let mut opened = vec![];
+        for redirect in redirects {
+            match redirect {
+                Redirect::Read(fd, path) => {
+                    opened.push(Opened::File(*fd, open(path, File::open(path))?))
+                }
+                Redirect::Write(fd, path) => {
+                    let file = OpenOptions::new()
+                        .write(true)
+                        .create(true)
+                        .truncate(true)
+                        .open(path);
+                    opened.push(Opened::File(*fd, open(path, file)?));
+                }
+                Redirect::Append(fd, path) => {
+                    let file = OpenOptions::new().append(true).create(true).open(path);
+                    opened.push(Opened::File(*fd, open(path, file)?));
+                }
+                Redirect::WriteAll(path) => {
+                    let file = OpenOptions::new()
+                        .write(true)
+                        .create(true)
+                        .truncate(true)
+                        .open(path);
+                    opened.push(Opened::File(STDOUT_FILENO, open(path, file)?));
+                    opened.push(Opened::Dup(STDERR_FILENO, STDOUT_FILENO));
+                }
+                Redirect::Dup(fd, target) => opened.push(Opened::Dup(*fd, *target)),
+            }
+        }
+        Ok(Self { opened })
+    }
+
+    /// Points the file descriptors at their targets, in order. Fails like
+    /// `9: Bad file descriptor` when a target isn't open.
+    pub fn apply(&self) -> Result<(), String> {
+        for opened in &self.opened {
+            let res = match opened {
+                Opened::File(fd, file) => dup2(file.as_raw_fd(), *fd).map_err(|e| (*fd, e)),
+                Opened::Dup(fd, target) => dup2(*target, *fd).map_err(|e| (*target, e)),
+            };
+            if let Err((fd, e)) = res {
+                return Err(format!("{}: {}", fd, strerror(e)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the redirections to the shell itself, for running a builtin.
+    /// On failure the file descriptors are restored already.
+    pub fn apply_saved(&self) -> Result<Saved, String> {
+        let mut saved = Saved { saved: vec![] };
+        for opened in &self.opened {
+            let fd = match opened {
+                Opened::File(fd, _) | Opened::Dup(fd, _) => *fd,
+            };
+            if !saved.saved.iter().any(|(saved, _)| *saved == fd) {
+                saved.saved.push((fd, dup(fd).ok()));
+            }
+        }
+        match self.apply() {
+            Ok(()) => Ok(saved),
+            Err(e) => {
+                saved.restore();
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Saved {
+    pub fn restore(self) {
+        for (fd, copy) in self.saved.into_iter().rev() {
+            match copy {
+                Some(copy) => {
+                    let _ = dup2(copy, fd);
+                    let _ = close(copy);
+                }
+                None => {
+                    let _ = close(fd);
+                }
+            }
+        }
+    }
+}
+
+/// The C library's message for e, which names EBADF the way shells do
+fn strerror(e: Errno) -> String {
+    let message = unsafe { CStr::from_ptr(libc::strerror(e as i32)) };
+    message.to_string_lossy().into_owned()
+}
+
+fn open(path: &str, file: io::Result<File>) -> Result<OwnedFd, String> {
+    match file {
+        Ok(file) => Ok(file.into()),
+        Err(e) => Err(format!(
+            "{}: {}",
+            path,
+            Errno::from_raw(e.raw_os_error().unwrap_or(0)).desc()
+        )),
+    }
+}
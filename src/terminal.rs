@@ -0,0 +1,64 @@
+use std::{io::stdin, os::fd::AsRawFd};
+
+use nix::{
+    sys::{
+        signal::{killpg, signal, SigHandler, Signal},
+        termios::{tcgetattr, tcsetattr, SetArg, Termios},
+    },
+    unistd::{getpgrp, getpid, isatty, setpgid, tcgetpgrp, tcsetpgrp, Pid},
+};
+
+/// The controlling terminal of an interactive shell, which is handed to
+/// whichever job runs in the foreground
+#[derive(Debug)]
+pub struct Terminal {
+    /// The shell's own process group
+    pgid: Pid,
+    /// The modes the shell reads commands in
+    modes: Termios,
+}
+
+impl Terminal {
+    /// Puts the shell in its own process group in the foreground of the
+    /// terminal on stdin. Returns None if stdin is not a terminal.
+    pub fn new() -> Option<Self> {
+        if !isatty(stdin().as_raw_fd()).unwrap_or(false) {
+            return None;
+        }
+        // started in the background, wait until the user brings us forward
+        while tcgetpgrp(stdin()).ok()? != getpgrp() {
+            let _ = killpg(getpgrp(), Signal::SIGTTIN);
+        }
+        // a background shell must still be able to call tcsetpgrp
+        unsafe {
+            let _ = signal(Signal::SIGTTIN, SigHandler::SigIgn);
+            let _ = signal(Signal::SIGTTOU, SigHandler::SigIgn);
+        }
+        let pgid = getpid();
+        // fails harmlessly when the shell already leads its session
+        let _ = setpgid(pgid, pgid);
+        let _ = tcsetpgrp(stdin(), pgid);
+        Some(Self {
+            pgid,
+            modes: tcgetattr(stdin()).ok()?,
+        })
+    }
+
+    /// Gives the terminal to a foreground job, restoring the modes it had
+    /// when it was stopped
+    pub fn give(&self, pgid: Pid, modes: Option<&Termios>) {
+        if let Some(modes) = modes {
+            let _ = tcsetattr(stdin(), SetArg::TCSADRAIN, modes);
+        }
+        let _ = tcsetpgrp(stdin(), pgid);
+    }
+
+    /// Takes the terminal back once the foreground job is done or stopped,
+    /// returning the modes the job left it in
+    pub fn reclaim(&self) -> Option<Termios> {
+        let _ = tcsetpgrp(stdin(), self.pgid);
+        let modes = tcgetattr(stdin()).ok();
+        let _ = tcsetattr(stdin(), SetArg::TCSADRAIN, &self.modes);
+        modes
+    }
+}
@@ -0,0 +1,153 @@
+use std::{collections::BTreeMap, env};
+
+#[derive(Debug)]
+struct Var {
+    value: String,
+    exported: bool,
+}
+
+/// Shell variables, the exported ones make up the environment of children
+#[derive(Debug)]
+pub struct Variables {
+    vars: BTreeMap<String, Var>,
+    /// `$0` followed by the positional parameters
+    args: Vec<String>,
+    /// `$!`, the pid of the last process of the latest background job
+    last_background: Option<i32>,
+}
+
+impl Variables {
+    /// Starts from the environment the shell was launched with
+    pub fn new() -> Self {
+        Variables {
+            vars: env::vars()
+                .map(|(name, value)| {
+                    (
+                        name,
+                        Var {
+                            value,
+                            exported: true,
+                        },
+                    )
+                })
+                .collect(),
+            args: env::args().take(1).collect(),
+            last_background: None,
+        }
+    }
+
+    /// Sets `$0` and the positional parameters
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    /// `$0`, `$1`, ...
+    pub fn arg(&self, n: usize) -> Option<&str> {
+        self.args.get(n).map(|arg| arg.as_str())
+    }
+
+    pub fn last_background(&self) -> Option<i32> {
+        self.last_background
+    }
+
+    pub fn set_last_background(&mut self, pid: i32) {
+        self.last_background = Some(pid);
+    }
+
+    /// `$1` onwards, what `$@` expands to
+    pub fn positional(&self) -> &[String] {
+        self.args.get(1..).unwrap_or_default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(|var| var.value.as_str())
+    }
+
+    /// Sets a variable, keeping it exported if it already was
+    pub fn set(&mut self, name: &str, value: String) {
+        match self.vars.get_mut(name) {
+            Some(var) => var.value = value,
+            None => {
+                self.vars.insert(
+                    name.to_string(),
+                    Var {
+                        value,
+                        exported: false,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Marks a variable as exported, creating it empty if needed
+    pub fn export(&mut self, name: &str) {
+        self.vars
+            .entry(name.to_string())
+            .or_insert(Var {
+                value: String::new(),
+                exported: false,
+            })
+            .exported = true;
+    }
+
+    pub fn unset(&mut self, name: &str) {
+        self.vars.remove(name);
+    }
+
+    /// `NAME=value` strings for `execve`, overridden by assignments
+    pub fn environ(&self, assignments: &[(String, String)]) -> Vec<String> {
+        let mut env: BTreeMap<&str, &str> = self
+            .vars
+            .iter()
+            .filter(|(_, var)| var.exported)
+            .map(|(name, var)| (name.as_str(), var.value.as_str()))
+            .collect();
+        for (name, value) in assignments {
+            env.insert(name, value);
+        }
+        env.iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.vars.keys().map(|name| name.as_str())
+    }
+
+    /// Every variable as `NAME=value`, for `set`
+    pub fn list(&self) -> String {
+        let mut res = String::new();
+        for (name, var) in &self.vars {
+            res += &format!("{}={}\n", name, quote(&var.value));
+        }
+        res
+    }
+
+    /// Exported variables as `export NAME=value`, for `export`
+    pub fn list_exported(&self) -> String {
+        let mut res = String::new();
+        for (name, var) in self.vars.iter().filter(|(_, var)| var.exported) {
+            res += &format!("export {}={}\n", name, quote(&var.value));
+        }
+        res
+    }
+}
+
+/// Whether name can be used as a variable name
+pub fn valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Single quotes value if the lexer would not read it back as one word
+fn quote(value: &str) -> String {
+    if !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-+=/.,:@%".contains(c))
+    {
+        return value.to_string();
+    }
+    format!("'{}'", value.replace('\'', r#"'\''"#))
+}
@@ -0,0 +1,549 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::thread::sleep;
+use std::time::Duration;
+
+const CARGO_DIR: &str = env!("CARGO_MANIFEST_DIR");
+
+/// Feeds input to tsh line by line and returns everything it printed
+fn driver(input: &str) -> String {
+    feed(&["-p"], input)
+}
+
+/// Like driver, with the prompts printed before each line
+fn prompted(input: &str) -> String {
+    feed(&[], input)
+}
+
+fn feed(args: &[&str], input: &str) -> String {
+    let mut child = Command::new(format!("{}/{}", CARGO_DIR, "target/debug/tsh"))
+        .args(args)
+        .current_dir(format!("{}/{}", CARGO_DIR, "bin"))
+        .env("HISTFILE", "")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("tsh not found");
+
+    let mut stdin = child.stdin.take().unwrap();
+    stdin
+        .write_all(input.as_bytes())
+        .expect("unable to write to pipe");
+    drop(stdin);
+
+    let mut output = String::new();
+    child
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_string(&mut output)
+        .expect("Failed to read stdout");
+    child.wait().unwrap();
+    output
+}
+
+/// Runs tsh with args instead of feeding it stdin
+fn run(args: &[&str]) -> (String, i32) {
+    let output = Command::new(format!("{}/{}", CARGO_DIR, "target/debug/tsh"))
+        .args(args)
+        .current_dir(format!("{}/{}", CARGO_DIR, "bin"))
+        .stdin(Stdio::null())
+        .output()
+        .expect("tsh not found");
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        output.status.code().unwrap(),
+    )
+}
+
+/// Types each line of keys at an interactive tsh on a pseudo terminal and
+/// returns the lines it printed, leaving out the ones it drew while editing
+fn typed(lines: &[&str]) -> Vec<String> {
+    let pty = nix::pty::openpty(None, None).expect("no pseudo terminal");
+    let mut child = Command::new(format!("{}/{}", CARGO_DIR, "target/debug/tsh"))
+        .current_dir(format!("{}/{}", CARGO_DIR, "bin"))
+        .env("HISTFILE", "")
+        .stdin(Stdio::from(pty.slave))
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("tsh not found");
+
+    let mut terminal = File::from(pty.master);
+    for line in lines {
+        sleep(Duration::from_millis(300));
+        terminal
+            .write_all(line.as_bytes())
+            .expect("unable to write to terminal");
+    }
+
+    let mut output = String::new();
+    child
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_string(&mut output)
+        .expect("Failed to read stdout");
+    child.wait().unwrap();
+    output
+        .lines()
+        .filter(|line| !line.contains('\x1b'))
+        .map(str::to_string)
+        .collect()
+}
+
+macro_rules! test {
+    ($name:ident, $input:expr, $expected:expr) => {
+        #[test]
+        fn $name() {
+            similar_asserts::assert_eq!(driver($input), $expected);
+        }
+    };
+}
+
+test!(
+    pipeline,
+    "/bin/echo hello world | /usr/bin/tr a-z A-Z | /bin/cat\n",
+    "HELLO WORLD\n"
+);
+test!(
+    pipeline_syntax_error,
+    "/bin/echo a | | /bin/cat\n/bin/echo a |\n",
+    "tsh: syntax error near unexpected token `|' at column 15\n\
+     tsh: syntax error near unexpected token `newline' at column 14\n"
+);
+test!(
+    redirect,
+    "/bin/echo one > ../target/tsh-redirect\n\
+     /bin/echo two >> ../target/tsh-redirect\n\
+     /bin/cat < ../target/tsh-redirect\n\
+     /bin/ls ../target/tsh-missing 2>&1 | /usr/bin/wc -l\n",
+    "one\ntwo\n1\n"
+);
+test!(
+    redirect_missing_file,
+    "/bin/cat < ../target/tsh-missing &\njobs\n",
+    "tsh: ../target/tsh-missing: No such file or directory\n\n"
+);
+test!(
+    redirect_bad_fd,
+    "cd . 9> ../target/tsh-redirect-fd\necho $?\npwd >&9\necho $?\n/bin/echo a >&9\necho $?\n",
+    "0\ntsh: 9: Bad file descriptor\n1\ntsh: 9: Bad file descriptor\n1\n"
+);
+test!(
+    path_lookup,
+    "echo hello | tr a-z A-Z\nhash -r\nhash\ntype jobs ./myspin\nnosuch\n",
+    "HELLO\nhash: hash table empty\n\
+     jobs is a shell builtin\n./myspin is ./myspin\n\
+     nosuch: Command not found\n"
+);
+test!(
+    exit_status,
+    "false\necho $?\nsh -c 'exit 3' | sh -c 'exit 5'\necho \"$?\" '$?'\nnosuch\necho $?\n",
+    "1\n5 $?\nnosuch: Command not found\n127\n"
+);
+test!(
+    command_lists,
+    "echo a; echo b\nfalse && echo no || echo yes\nfalse; echo $?\necho 'a;b' && echo \"c||d\"\necho a &&\n",
+    "a\nb\nyes\n1\na;b\nc||d\ntsh: syntax error near unexpected token `newline' at column 10\n"
+);
+test!(
+    quoting,
+    "echo a\"b c\"d foo\\ bar 'x|y' \"q\\\"z\" tsh> \\046\necho $? '$?'\n",
+    "ab cd foo bar x|y q\"z tsh> \\046\n0 $?\n"
+);
+test!(
+    parse_errors,
+    "echo a; ; b\necho ok&& echo ok\necho 'abc\n",
+    "tsh: syntax error near unexpected token `;' at column 9\n\
+     ok\nok\n\
+     tsh: unterminated quote at column 6\n"
+);
+test!(
+    empty_expansion,
+    "E=\n/usr/bin/printf '<%s>' a $E b \"$E\" '' ''$E ${E:-} x$E\n\
+     /bin/echo\n$E /bin/echo c\n",
+    "<a><b><><><><x>\nc\n"
+);
+test!(
+    variables,
+    "A=hello\n\
+     echo $A ${A}world '$A' ${B:-def} \"${B:-x y}\"\n\
+     sh -c 'echo [$A]'\n\
+     export A\n\
+     sh -c 'echo [$A]'\n\
+     B=temp sh -c 'echo [$B]'\n\
+     echo [$B]\n\
+     unset A\n\
+     echo [$A] ${A\n",
+    "hello helloworld $A def x y\n[]\n[hello]\n[temp]\n[]\n\
+     tsh: unterminated `${' at column 11\n"
+);
+test!(
+    directories,
+    "cd /usr\npwd\ncd bin\ncd -\ncd ..\npwd -L\nHOME=/usr\ncd\n/bin/echo $PWD $OLDPWD ~/lib\n\
+     pushd /\npushd bin\ndirs -v\npushd\npopd\npopd\npopd\ncd /missing\n",
+    "/usr\n/usr\n/\n/usr / /usr/lib\n/ ~\n/bin / ~\n 0  /bin\n 1  /\n 2  ~\n/ /bin ~\n\
+     /bin ~\n~\ntsh: popd: directory stack empty\n\
+     tsh: cd: /missing: No such file or directory\n"
+);
+test!(
+    kill,
+    "kill -l 9 INT 130\nkill -s FOO 1\nkill %3\nkill -0 nope\n/bin/echo $?\n",
+    "KILL\n2\nINT\n\
+     tsh: kill: FOO: invalid signal specification\n\
+     %3: No such job\n\
+     tsh: kill: nope: arguments must be process or job IDs\n1\n"
+);
+#[test]
+fn default_job() {
+    // bg continues %+, the last job, after which the stopped %1 is the one
+    // fg brings back
+    let output = driver(
+        "/bin/sleep 0.5 &\n/bin/sleep 0.3 &\nkill -STOP %1\n/bin/sleep 0.1\nkill -STOP %2\n/bin/sleep 0.1\n\
+         bg\njobs\nfg\necho $?\njobs\n",
+    );
+    let pids = regex::Regex::new(r"\d{3,}").unwrap();
+    similar_asserts::assert_eq!(
+        pids.replace_all(&output, "N"),
+        "[1] (N) /bin/sleep 0.5 &\n[2] (N) /bin/sleep 0.3 &\n\
+         Job [1] (N) stopped by signal 19\nJob [2] (N) stopped by signal 19\n\
+         [2] (N) /bin/sleep 0.3 &\n\
+         [1] (N) Stopped /bin/sleep 0.5 &\n[2] (N) Running /bin/sleep 0.3 &\n\
+         [1] (N) Foreground /bin/sleep 0.5 &\n[2] (N) Running /bin/sleep 0.3 &\n\
+         0\n\n"
+    );
+}
+
+test!(
+    job_errors,
+    "fg a\nbg 9999999\nfg %2\nbg\n",
+    "fg: argument must be a PID or %jobid\n\
+     (9999999): No such process\n\
+     %2: No such job\n\
+     bg command requires PID or %jobid argument\n"
+);
+#[test]
+fn history() {
+    let lines = "echo a\r echo b\rhistory 2\recho !! !-3 !$\r!ec\r^a^c\r!nope\recho '!!'\r\
+                 history -d 1\rhistory -d 9\rhistory\rhistory -c\rhistory\r\x04";
+    similar_asserts::assert_eq!(
+        typed(&lines.split_inclusive(['\r', '\x04']).collect::<Vec<_>>()).join("\n"),
+        "a\nb\n    2   echo b\n    3  history 2\n\
+         echo history 2 echo a 2\nhistory 2 echo a 2\n\
+         echo history 2 echo a 2\nhistory 2 echo a 2\n\
+         echo history 2 echo c 2\nhistory 2 echo c 2\n\
+         tsh: !nope: event not found\n!!\n\
+         tsh: history: 9: history position out of range\n\
+         \x20   1   echo b\n    2  history 2\n    3  echo history 2 echo a 2\n\
+         \x20   4  echo history 2 echo a 2\n    5  echo history 2 echo c 2\n\
+         \x20   6  echo '!!'\n    7  history -d 1\n    8  history -d 9\n    9  history\n\
+         \x20   1  history"
+    );
+    // a quoted newline is part of the entry
+    similar_asserts::assert_eq!(
+        typed(&["/bin/echo \"a\r", "b\"\r", "!!\r", "\x04"]),
+        ["a", "b", "/bin/echo \"a", "b\"", "a", "b"]
+    );
+    // lines read from a pipe are neither expanded nor kept
+    similar_asserts::assert_eq!(driver("echo a\necho !!\nhistory\n"), "a\n!!\n");
+}
+
+#[test]
+fn command_string() {
+    similar_asserts::assert_eq!(
+        run(&[
+            "-c",
+            "/bin/echo $0 $#; /usr/bin/printf '<%s>' \"$@\"; fg %1",
+            "name",
+            "a",
+            "b c"
+        ]),
+        ("name 2\n<a><b c>tsh: fg: no job control\n".to_string(), 1)
+    );
+    similar_asserts::assert_eq!(
+        run(&["-c", "true &&\n/bin/echo yes\nfalse ||\n\n/bin/echo 'a\nb'"]),
+        ("yes\na\nb\n".to_string(), 0)
+    );
+}
+
+#[test]
+fn job_specs() {
+    similar_asserts::assert_eq!(
+        run(&[
+            "-c",
+            "/bin/sleep 1 & /bin/sleep 2 &\n\
+             kill -0 %+ %- %% %?2 %/bin/sleep\\ 1 && /bin/echo found\n\
+             kill -0 %?sleep; kill -0 %/bin/sleep; kill -0 %3",
+        ]),
+        (
+            "found\n\
+             kill: %?sleep: ambiguous job spec\n\
+             kill: %/bin/sleep: ambiguous job spec\n\
+             %3: No such job\n"
+                .to_string(),
+            1
+        )
+    );
+}
+
+#[test]
+fn wait() {
+    similar_asserts::assert_eq!(
+        run(&[
+            "-c",
+            "/bin/sleep 0.2 & /bin/sh -c 'exit 3' &\n\
+             wait %2; /bin/echo $?; wait; /bin/echo $?\n\
+             /bin/sh -c 'exit 4' & wait $!; /bin/echo $?\n\
+             /bin/sh -c 'sleep 0.2; exit 5' & /bin/sh -c 'exit 6' &\n\
+             wait -n; /bin/echo $?; wait -n; /bin/echo $?; wait -n; /bin/echo $?\n\
+             wait %9; wait 1",
+        ]),
+        (
+            "3\n0\n4\n6\n5\n127\n\
+             %9: No such job\n\
+             tsh: wait: pid 1 is not a child of this shell\n"
+                .to_string(),
+            127
+        )
+    );
+    // `-n` with jobs returns when the first of them finishes
+    similar_asserts::assert_eq!(
+        run(&[
+            "-c",
+            "/bin/sh -c 'sleep 0.4; exit 7' & /bin/sh -c 'sleep 0.1; exit 8' &\n\
+             /bin/sh -c 'exit 9' & /bin/sleep 0.2\n\
+             wait -n %1 %2; /bin/echo $?; wait -n %1 %3; /bin/echo $?\n\
+             wait -n %1 1; /bin/echo $?; wait -n 1",
+        ]),
+        (
+            "8\n9\ntsh: wait: pid 1 is not a child of this shell\n7\n\
+             tsh: wait: pid 1 is not a child of this shell\n"
+                .to_string(),
+            127
+        )
+    );
+}
+
+#[test]
+fn disown() {
+    similar_asserts::assert_eq!(
+        run(&[
+            "-c",
+            "/bin/sleep 0.2 & /bin/sleep 0.2 & disown -h %1; disown -r; jobs\n\
+             disown %1; disown -x; /bin/echo done",
+        ]),
+        (
+            "\n%1: No such job\n\
+             tsh: disown: -x: invalid option\n\
+             disown: usage: disown [-h] [-ar] [jobspec ...]\n\
+             done\n"
+                .to_string(),
+            0
+        )
+    );
+}
+
+#[test]
+fn notify() {
+    let output = driver("set -b\n/bin/sh -c 'exit 2' &\n/bin/sleep 0.2\nset -o\nset +b\nset +o\n");
+    let lines: Vec<_> = output.lines().skip(1).collect();
+    similar_asserts::assert_eq!(
+        lines,
+        [
+            "[1]+  Exit 2                  /bin/sh -c 'exit 2'",
+            "notify         \ton",
+            "set +o notify",
+        ]
+    );
+}
+
+#[test]
+fn time() {
+    let (output, status) = run(&["-c", "time /bin/echo hi | /bin/cat; time /bin/false"]);
+    let labels: Vec<_> = output
+        .lines()
+        .map(|line| line.split('\t').next().unwrap())
+        .collect();
+    similar_asserts::assert_eq!(
+        (labels, status),
+        (
+            vec!["hi", "", "real", "user", "sys", "", "real", "user", "sys"],
+            1
+        )
+    );
+    // a busy background job's CPU time is not the timed job's
+    let (output, _) = run(&[
+        "-c",
+        "/bin/sh -c 'while :; do :; done' & time /bin/sleep 0.5; kill %1",
+    ]);
+    let user = output.lines().nth(2).unwrap_or_default();
+    assert!(user.starts_with("user\t0m0.0"), "{}", user);
+    // `time` alone takes no time, a timed background job is refused
+    similar_asserts::assert_eq!(
+        run(&["-c", "time\ntime /bin/sleep 0.1 &\necho $?"]),
+        (
+            "\nreal\t0m0.000s\nuser\t0m0.000s\nsys\t0m0.000s\n\
+             tsh: time: can't time a background job at column 21\n2\n"
+                .to_string(),
+            0
+        )
+    );
+}
+
+#[test]
+fn jobs_options() {
+    let (output, status) = run(&[
+        "-c",
+        "/bin/sleep 0.3 & /bin/sh -c 'exit 2' | /bin/sleep 0.3 & /bin/sleep 0.1\n\
+         jobs -p; jobs -l %2; jobs --json %1; jobs -r -s %1\n\
+         jobs -x /bin/echo %1 %2 x; jobs -x /bin/false; jobs -x\n\
+         jobs %3; wait",
+    ]);
+    let pids = regex::Regex::new(r"\d{3,}").unwrap();
+    similar_asserts::assert_eq!(
+        (pids.replace_all(&output, "N").to_string(), status),
+        (
+            "N\n\
+             N\n\
+             [2] (N) Running /bin/sh -c 'exit 2' | /bin/sleep 0.3 &\n    \
+             N Exit 2\n    \
+             N Running\n\
+             [{\"jid\":1,\"pgid\":N,\"state\":\"Running\",\"command\":\"/bin/sleep 0.3 &\",\
+             \"processes\":[{\"pid\":N,\"exit\":null,\"signal\":null}]}]\n\
+             [1] (N) Running /bin/sleep 0.3 &\n\
+             N N x\n\
+             tsh: jobs: -x: option requires an argument\n\
+             %3: No such job\n\
+             \n"
+            .to_string(),
+            0
+        )
+    );
+}
+
+#[test]
+fn job_states() {
+    let (output, status) = run(&[
+        "-c",
+        "/bin/sleep 0.1 & /bin/sh -c 'exit 3' & /bin/sleep 5 & /bin/sleep 0.3; \
+         jobs; jobs; kill -9 %3; /bin/sleep 0.1; jobs -s; jobs; bg %3; jobs",
+    ]);
+    let pids = regex::Regex::new(r"\d{3,}").unwrap();
+    similar_asserts::assert_eq!(
+        (pids.replace_all(&output, "N").to_string(), status),
+        (
+            "[1] (N) Done /bin/sleep 0.1 &\n\
+             [2] (N) Exit 3 /bin/sh -c 'exit 3' &\n\
+             [3] (N) Running /bin/sleep 5 &\n\
+             [3] (N) Running /bin/sleep 5 &\n\
+             Job [3] (N) terminated by signal 9\n\
+             \n\
+             [3] (N) Killed /bin/sleep 5 &\n\
+             tsh: bg: no job control\n\
+             \n"
+            .to_string(),
+            0
+        )
+    );
+}
+
+#[test]
+fn line_editor() {
+    similar_asserts::assert_eq!(
+        typed(&[
+            // Ctrl-A, Alt-F twice, Ctrl-E, Left, Ctrl-K
+            "/bin/echo wörld\x01\x1bf\x1bf héllo\x05 日本\x1b[D\x0b\r",
+            // Ctrl-W, Ctrl-Y
+            "/bin/echo one two\x17three \x19\r",
+            // Ctrl-U, Ctrl-C
+            "/bin/echo no\x15/bin/echo yes\r",
+            "/bin/echo no\x03",
+            // Up twice, Down
+            "\x1b[A\x1b[A\x1b[B\r",
+            "\x04",
+        ]),
+        ["héllo wörld 日", "one three two", "yes", "yes"]
+    );
+}
+
+#[test]
+fn history_search() {
+    similar_asserts::assert_eq!(
+        typed(&[
+            "/bin/echo spin one\r",
+            "/bin/echo two\r",
+            // Ctrl-R twice goes past the newer match, Enter takes it for editing
+            "\x12o\x12\r\x05!\r",
+            // found by its letters in order
+            "\x12etw\r\r",
+            // Ctrl-G gives up
+            "/bin/echo\x12zz\x07 back\r",
+            "\x04",
+        ]),
+        ["spin one", "two", "spin one!", "two", "back"]
+    );
+}
+
+#[test]
+fn completion() {
+    let dir = format!("{}/target/tsh-complete", CARGO_DIR);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(format!("{}/sub", dir)).unwrap();
+    std::fs::write(format!("{}/some file", dir), "").unwrap();
+    std::fs::write(format!("{}/sub/inner", dir), "").unwrap();
+    similar_asserts::assert_eq!(
+        typed(&[
+            "/bin/echo ../target/tsh-complete/so\t\r",
+            "/bin/ls ../target/tsh-complete/su\t\r",
+            "/bin/echo \"../target/tsh-complete/so\t\r",
+            "typ\t hash\r",
+            "ZZVAR=1\r",
+            "/bin/echo ${ZZV\t\r",
+            "complete -W 'alpha beta' echo\r",
+            "echo al\t\r",
+            "complete -p echo cd\r",
+            "\x04",
+        ]),
+        [
+            "../target/tsh-complete/some file",
+            "inner",
+            "../target/tsh-complete/some file",
+            "hash is a shell builtin",
+            "1",
+            "alpha",
+            "complete -W 'alpha beta' echo",
+            "complete -W '-L -P' -d cd",
+        ]
+    );
+}
+
+#[test]
+fn prompts() {
+    let output = prompted(concat!(
+        "PS1='\\s[\\?] \\W \\j/\\R/\\S> ' PS2='\\[\\e[1m\\]...\\[\\e[0m\\] '\n",
+        "false\n",
+        "/bin/sleep 1 &\n",
+        "echo 'a\nb' &&\n",
+        "echo c\n",
+        "PS1='\\101\\q\\\\ '\n",
+        "unset PS1\n",
+    ));
+    let pids = regex::Regex::new(r"\d{3,}").unwrap();
+    similar_asserts::assert_eq!(
+        pids.replace_all(&output, "N"),
+        concat!(
+            "tsh> tsh[0] bin 0/0/0> tsh[1] bin 0/0/0> [1] (N) /bin/sleep 1 &\n",
+            "tsh[0] bin 1/1/0> \x1b[1m...\x1b[0m \x1b[1m...\x1b[0m a\nb\nc\n",
+            "tsh[0] bin 1/1/0> A\\q\\ tsh> "
+        )
+    );
+}
+
+#[test]
+fn script() {
+    similar_asserts::assert_eq!(
+        run(&["../tests/args.tsh", "one", "two"]),
+        ("../tests/args.tsh: one two\nmulti\nline\n".to_string(), 2)
+    );
+}